@@ -0,0 +1,144 @@
+/**
+ * thegrep - build script
+ *
+ * Turns the Unicode Character Database files vendored in data/ucd into
+ * Rust tables of char ranges, so the matcher never has to fetch or parse
+ * them at runtime. The output is include!'d by src/unicode.rs.
+ */
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const UCD_DIR: &str = "data/ucd";
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={}", UCD_DIR);
+
+    let mut out = String::from("// @generated by build.rs from data/ucd, do not edit\n\n");
+    out.push_str(&table(
+        "GENERAL_CATEGORY",
+        &["DerivedGeneralCategory.txt"],
+    ));
+    out.push_str(&table(
+        "BINARY_PROPERTY",
+        &["PropList.txt", "DerivedCoreProperties.txt"],
+    ));
+    out.push_str(&table("SCRIPT", &["Scripts.txt"]));
+    out.push_str(&case_folding("CASE_FOLDING", "CaseFolding.txt"));
+
+    let dest = Path::new(&env::var("OUT_DIR").unwrap()).join("unicode_tables.rs");
+    fs::write(dest, out).unwrap();
+}
+
+/**
+ * Build one `&[(name, &[(lo, hi)])]` table, sorted by name, out of the
+ * `range ; value` lines of the given UCD files.
+ */
+fn table(name: &str, files: &[&str]) -> String {
+    let mut values: BTreeMap<String, Vec<(u32, u32)>> = BTreeMap::new();
+    for file in files {
+        let path = Path::new(UCD_DIR).join(file);
+        let text = fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        for (lo, hi, value) in text.lines().filter_map(parse_line) {
+            values.entry(value).or_default().push((lo, hi));
+        }
+    }
+
+    let mut out = format!("pub const {}: &[(&str, &[(char, char)])] = &[\n", name);
+    for (value, ranges) in values {
+        out.push_str(&format!("    ({:?}, &[", value));
+        for (lo, hi) in merge(ranges) {
+            out.push_str(&format!("('\\u{{{:x}}}', '\\u{{{:x}}}'), ", lo, hi));
+        }
+        out.push_str("]),\n");
+    }
+    out.push_str("];\n\n");
+    out
+}
+
+/**
+ * Build a `&[(c, &[chars])]` table, sorted by c, that gives every char
+ * with a simple case folding the set of chars that fold the same way,
+ * e.g. k, K and U+212A KELVIN SIGN. Only statuses C and S are simple.
+ */
+fn case_folding(name: &str, file: &str) -> String {
+    let path = Path::new(UCD_DIR).join(file);
+    let text = fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let mut orbits: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split('#').next().unwrap().split(';').map(str::trim).collect();
+        if let [code, "C", folded, ..] | [code, "S", folded, ..] = fields[..] {
+            let code = u32::from_str_radix(code, 16).unwrap();
+            let folded = u32::from_str_radix(folded, 16).unwrap();
+            orbits.entry(folded).or_insert_with(|| vec![folded]).push(code);
+        }
+    }
+
+    let mut members: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+    for mut orbit in orbits.into_values() {
+        orbit.sort();
+        for &c in &orbit {
+            members.insert(c, orbit.clone());
+        }
+    }
+
+    let mut out = format!("pub const {}: &[(char, &[char])] = &[\n", name);
+    for (c, orbit) in members {
+        out.push_str(&format!("    ('\\u{{{:x}}}', &[", c));
+        for member in orbit {
+            out.push_str(&format!("'\\u{{{:x}}}', ", member));
+        }
+        out.push_str("]),\n");
+    }
+    out.push_str("];\n\n");
+    out
+}
+
+// "0041..005A    ; Lu" -> (0x41, 0x5A, "Lu"), comments and blank lines -> None
+fn parse_line(line: &str) -> Option<(u32, u32, String)> {
+    let line = line.split('#').next().unwrap().trim();
+    if line.is_empty() {
+        return None;
+    }
+    let mut fields = line.split(';').map(str::trim);
+    let range = fields.next()?;
+    let value = fields.next()?.to_string();
+    let mut bounds = range.split("..");
+    let lo = u32::from_str_radix(bounds.next()?, 16).ok()?;
+    let hi = match bounds.next() {
+        Some(hi) => u32::from_str_radix(hi, 16).ok()?,
+        None => lo,
+    };
+    Some((lo, hi, value))
+}
+
+/**
+ * Sort and join adjacent ranges, dropping the surrogate block since
+ * those code points are not valid chars.
+ */
+fn merge(mut ranges: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+    ranges.sort();
+    let mut merged: Vec<(u32, u32)> = Vec::new();
+    for (lo, hi) in ranges {
+        for (lo, hi) in without_surrogates(lo, hi) {
+            match merged.last_mut() {
+                Some(last) if lo <= last.1 + 1 => last.1 = last.1.max(hi),
+                _ => merged.push((lo, hi)),
+            }
+        }
+    }
+    merged
+}
+
+fn without_surrogates(lo: u32, hi: u32) -> Vec<(u32, u32)> {
+    let mut parts = Vec::new();
+    if lo < 0xD800 {
+        parts.push((lo, hi.min(0xD7FF)));
+    }
+    if hi > 0xDFFF {
+        parts.push((lo.max(0xE000), hi));
+    }
+    parts
+}
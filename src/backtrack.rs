@@ -0,0 +1,750 @@
+use super::charclass::CharClass;
+use super::error::Error;
+use super::nfa::{self, Captures, Config, Look};
+use super::parser::{Assertion, Flags, Lookaround, Parser, AST};
+use super::unicode;
+use super::visit::Visitor;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/*
+ * Tar Heel egrep - backtracking matcher
+ *
+ * A backreference like (\w+) \1 has to remember what its group matched,
+ * which no finite automaton can do, so patterns that use them are run by
+ * walking the AST directly instead. Choices are tried in the same order
+ * the Pike VM gives them priority, and a path is dropped when it gets back
+ * to a state of the NFA it has already been in without moving, as it is
+ * in the Pike VM, so both find the same leftmost-first match. Lookaround
+ * runs here too, as a nested search at the current position; lookbehind
+ * tries each start that its bounded length allows and needs the nested
+ * match to end exactly where it is. Backtracking can take exponential
+ * time, so the steps taken from each start count against a budget and
+ * running out of it is an error. Long lines alone don't use it up.
+ */
+
+/**
+ * A pattern ready for the backtracking matcher.
+ */
+#[derive(Debug)]
+pub struct Backtrack {
+    ast: AST,
+    groups: usize,
+    names: Vec<Option<String>>,
+    step_limit: usize,
+}
+
+impl Backtrack {
+    /**
+     * Construct a backtracking matcher from a regular expression pattern.
+     */
+    pub fn from(regular_expression: &str) -> Result<Backtrack, Error> {
+        Backtrack::with_config(regular_expression, &Config::default())
+    }
+
+    /**
+     * Construct a backtracking matcher with the given Config.
+     */
+    pub fn with_config(regular_expression: &str, config: &Config) -> Result<Backtrack, Error> {
+        let ast = Parser::parse_with_flags(config.tokenizer(regular_expression), config.flags())?;
+        Backtrack::from_ast(ast, config)
+    }
+
+    /**
+     * Construct a backtracking matcher from an already parsed pattern.
+     */
+    pub fn from_ast(ast: AST, config: &Config) -> Result<Backtrack, Error> {
+        nfa::check_size(&ast, config.size_limit)?;
+        check_lookbehinds(&ast)?;
+        let groups = nfa::group_count(&ast);
+        let mut names = vec![None; groups + 1];
+        nfa::group_names(&ast, &mut names);
+        Ok(Backtrack {
+            ast,
+            groups,
+            names,
+            step_limit: config.step_limit,
+        })
+    }
+
+    /**
+     * Search mode: is there a match starting anywhere in the input?
+     */
+    pub fn is_match(&self, text: &str) -> Result<bool, Error> {
+        Ok(self.captures(text)?.is_some())
+    }
+
+    /**
+     * Find the leftmost match in text and the spans of all of its groups.
+     */
+    pub fn captures<'t>(&'t self, text: &'t str) -> Result<Option<Captures<'t>>, Error> {
+        let mut search = Search {
+            text,
+            slots: vec![None; 2 * (self.groups + 1)],
+            trail: vec![],
+            visited: vec![],
+            instances: HashMap::new(),
+            steps: 0,
+            limit: self.step_limit,
+        };
+        let starts = text.char_indices().map(|(at, _)| at).chain(Some(text.len()));
+        for start in starts {
+            search.slots.iter_mut().for_each(|slot| *slot = None);
+            search.trail.clear();
+            search.visited.clear();
+            search.steps = 0;
+            if let Some(end) = search.run(&self.ast, Flags::default(), start, None)? {
+                search.slots[0] = Some(start);
+                search.slots[1] = Some(end);
+                return Ok(Some(Captures::new(text, search.slots, &self.names)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/**
+ * What is left to match, as a linked list so that the choice points can
+ * share their tails instead of copying them.
+ */
+type Cont<'a> = Option<Rc<Link<'a>>>;
+
+struct Link<'a> {
+    frame: Frame<'a>,
+    next: Cont<'a>,
+}
+
+#[derive(Clone, Copy)]
+enum Frame<'a> {
+    // match this node, in the given copy of it, under these flags
+    Node(&'a AST, usize, Flags),
+    // the body of group index, which began at open, has matched
+    Close {
+        group: &'a AST,
+        instance: usize,
+        index: usize,
+        open: usize,
+    },
+    // between min and max more copies of body, after done of them
+    Repeat {
+        quantifier: &'a AST,
+        body: &'a AST,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+        done: usize,
+        instance: usize,
+        flags: Flags,
+    },
+    // the pattern matched, but didn't end where it had to
+    Fail,
+}
+
+/**
+ * Which of the states the NFA makes for a node a frame stands for.
+ */
+#[derive(Clone, Copy, PartialEq)]
+enum Part {
+    Start,
+    Close,
+    Split,
+}
+
+/**
+ * One state of the NFA: the node it is made for, the copy of that node
+ * it is in, since counted repetition copies its body, and which part.
+ */
+type State = (usize, usize, Part);
+
+// the copy that all of a loop's iterations share
+const LOOP: usize = usize::MAX;
+
+fn state(ast: &AST, instance: usize, part: Part) -> State {
+    (ast as *const AST as usize, instance, part)
+}
+
+impl Frame<'_> {
+    fn state(&self) -> Option<State> {
+        match *self {
+            Frame::Node(ast, instance, _) => Some(state(ast, instance, Part::Start)),
+            Frame::Close { group, instance, .. } => Some(state(group, instance, Part::Close)),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * A place to resume from when the current path fails.
+ */
+struct Choice<'a> {
+    at: usize,
+    cont: Cont<'a>,
+    trail: usize,
+    visited: usize,
+}
+
+/**
+ * State of one search over a text. Slots are written in place and the
+ * old values are kept on the trail, so backtracking can undo them. The
+ * states the current path went through are kept with where it was in
+ * the text at the time, and the copies of nodes that repetition makes
+ * are numbered as they are first needed.
+ */
+struct Search<'t> {
+    text: &'t str,
+    slots: Vec<Option<usize>>,
+    trail: Vec<(usize, Option<usize>)>,
+    visited: Vec<(usize, State)>,
+    instances: HashMap<(usize, usize, usize), usize>,
+    // taken from the current start, so the budget doesn't grow with the text
+    steps: usize,
+    limit: usize,
+}
+
+fn push<'a>(frame: Frame<'a>, next: Cont<'a>) -> Cont<'a> {
+    Some(Rc::new(Link { frame, next }))
+}
+
+impl<'t> Search<'t> {
+    /**
+     * Match ast starting at byte offset at, trying its choices in
+     * priority order until one of them gets to the end of the pattern,
+     * and return where that match ends. If end is given, only a match
+     * that ends there counts. A failed search leaves the slots as it
+     * found them.
+     */
+    fn run(&mut self, ast: &AST, flags: Flags, mut at: usize, end: Option<usize>) -> Result<Option<usize>, Error> {
+        let trail = self.trail.len();
+        let floor = self.visited.len();
+        let mut choices: Vec<Choice> = vec![];
+        let mut cont = push(Frame::Node(ast, 0, flags), None);
+
+        loop {
+            self.step()?;
+            let link = match cont {
+                Some(link) => link,
+                None if end.is_none_or(|end| end == at) => {
+                    self.visited.truncate(floor);
+                    return Ok(Some(at));
+                }
+                None => Rc::new(Link {
+                    frame: Frame::Fail,
+                    next: None,
+                }),
+            };
+            cont = link.next.clone();
+
+            let fresh = match link.frame.state() {
+                Some(state) => self.enter(state, at, floor),
+                None => true,
+            };
+            let ok = match link.frame {
+                _ if !fresh => false,
+                Frame::Node(ast, instance, flags) => match ast {
+                    AST::Char(_) | AST::AnyChar | AST::Class(_) => match self.next(at) {
+                        Some(c) if char_matches(ast, c, flags) => {
+                            at += c.len_utf8();
+                            true
+                        }
+                        _ => false,
+                    },
+                    AST::Empty => true,
+                    AST::Assertion(assertion) => Look::new(*assertion, flags).holds(self.prev(at), self.next(at)),
+                    AST::Backref(index) => match self.backref(*index, at, flags) {
+                        Some(end) => {
+                            at = end;
+                            true
+                        }
+                        None => false,
+                    },
+                    AST::Lookaround(kind, ast) => self.lookaround(*kind, ast, flags, at)?,
+                    AST::Catenation(lhs, rhs) => {
+                        cont = push(Frame::Node(lhs, instance, flags), push(Frame::Node(rhs, instance, flags), cont));
+                        true
+                    }
+                    AST::Alternation(lhs, rhs) => {
+                        choices.push(self.choice(at, push(Frame::Node(rhs, instance, flags), cont.clone())));
+                        cont = push(Frame::Node(lhs, instance, flags), cont);
+                        true
+                    }
+                    AST::Group { index, ast: body, .. } => {
+                        let close = Frame::Close {
+                            group: ast,
+                            instance,
+                            index: *index,
+                            open: at,
+                        };
+                        cont = push(Frame::Node(body, instance, flags), push(close, cont));
+                        true
+                    }
+                    AST::Flags(flags, ast) => {
+                        cont = push(Frame::Node(ast, instance, *flags), cont);
+                        true
+                    }
+                    AST::Lazy(quantifier) => {
+                        cont = push(repetition(quantifier, false, instance, flags), cont);
+                        true
+                    }
+                    quantifier => {
+                        cont = push(repetition(quantifier, true, instance, flags), cont);
+                        true
+                    }
+                },
+                Frame::Close { index, open, .. } => {
+                    self.save(2 * index, open);
+                    self.save(2 * index + 1, at);
+                    true
+                }
+                Frame::Fail => false,
+                Frame::Repeat { min: 0, max: Some(0), .. } => true,
+                Frame::Repeat { quantifier, body, min, max, greedy, done, instance, flags } => {
+                    // only counted repetition makes copies of its body, and the
+                    // ones after the count of x{n,} loop through the same states
+                    let copy = match quantifier {
+                        AST::Repeat { .. } if min == 0 && max.is_none() => self.instance(instance, quantifier, LOOP),
+                        AST::Repeat { .. } => self.instance(instance, quantifier, done),
+                        _ => instance,
+                    };
+                    let more = push(
+                        Frame::Node(body, copy, flags),
+                        push(
+                            Frame::Repeat {
+                                quantifier,
+                                body,
+                                min: min.saturating_sub(1),
+                                max: max.map(|max| max - 1),
+                                greedy,
+                                done: done + 1,
+                                instance,
+                                flags,
+                            },
+                            cont.clone(),
+                        ),
+                    );
+                    if min > 0 {
+                        cont = more;
+                        true
+                    } else if !self.enter(state(quantifier, copy, Part::Split), at, floor) {
+                        false
+                    } else if greedy {
+                        choices.push(self.choice(at, cont));
+                        cont = more;
+                        true
+                    } else {
+                        choices.push(self.choice(at, more));
+                        true
+                    }
+                }
+            };
+
+            if !ok {
+                match choices.pop() {
+                    Some(choice) => {
+                        self.undo(choice.trail);
+                        self.visited.truncate(choice.visited);
+                        at = choice.at;
+                        cont = choice.cont;
+                    }
+                    None => break,
+                }
+            }
+        }
+        self.undo(trail);
+        self.visited.truncate(floor);
+        Ok(None)
+    }
+
+    // does the lookaround hold at at? groups set inside a positive one
+    // are kept, the trail undoes them if the outer search backtracks
+    fn lookaround(&mut self, kind: Lookaround, ast: &AST, flags: Flags, at: usize) -> Result<bool, Error> {
+        let found = match kind {
+            Lookaround::Ahead | Lookaround::NotAhead => self.run(ast, flags, at, None)?.is_some(),
+            Lookaround::Behind | Lookaround::NotBehind => {
+                let (min, max) = width(ast);
+                let mut start = at;
+                let mut found = false;
+                for back in 0..=max.unwrap_or(min) {
+                    if back >= min && self.run(ast, flags, start, Some(at))?.is_some() {
+                        found = true;
+                        break;
+                    }
+                    match self.prev(start) {
+                        Some(c) => start -= c.len_utf8(),
+                        None => break,
+                    }
+                }
+                found
+            }
+        };
+        Ok(found == matches!(kind, Lookaround::Ahead | Lookaround::Behind))
+    }
+
+    // where the text at at ends if it repeats what group index matched
+    fn backref(&self, index: usize, at: usize, flags: Flags) -> Option<usize> {
+        let (start, end) = (self.slots[2 * index]?, self.slots[2 * index + 1]?);
+        let group = &self.text[start..end];
+        if !flags.case_insensitive {
+            return self.text[at..].starts_with(group).then(|| at + group.len());
+        }
+        let mut rest = self.text[at..].chars();
+        for expected in group.chars() {
+            let c = rest.next()?;
+            if !unicode::case_variants(expected).contains(&c) {
+                return None;
+            }
+        }
+        Some(self.text.len() - rest.as_str().len())
+    }
+
+    fn choice<'a>(&self, at: usize, cont: Cont<'a>) -> Choice<'a> {
+        Choice {
+            at,
+            cont,
+            trail: self.trail.len(),
+            visited: self.visited.len(),
+        }
+    }
+
+    // note that the path is in state at at, unless it has already been
+    // there since it last moved, which is where the Pike VM drops it
+    fn enter(&mut self, state: State, at: usize, floor: usize) -> bool {
+        let seen = self.visited[floor..]
+            .iter()
+            .rev()
+            .take_while(|(was, _)| *was == at)
+            .any(|(_, old)| *old == state);
+        if !seen {
+            self.visited.push((at, state));
+        }
+        !seen
+    }
+
+    // the number of a copy of quantifier's body inside copy instance
+    fn instance(&mut self, instance: usize, quantifier: &AST, copy: usize) -> usize {
+        let next = self.instances.len() + 1;
+        *self.instances.entry((instance, quantifier as *const AST as usize, copy)).or_insert(next)
+    }
+
+    fn save(&mut self, slot: usize, at: usize) {
+        self.trail.push((slot, self.slots[slot]));
+        self.slots[slot] = Some(at);
+    }
+
+    fn undo(&mut self, trail: usize) {
+        while self.trail.len() > trail {
+            let (slot, old) = self.trail.pop().unwrap();
+            self.slots[slot] = old;
+        }
+    }
+
+    fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        if self.steps > self.limit {
+            Err(Error::StepLimit { limit: self.limit })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn prev(&self, at: usize) -> Option<char> {
+        self.text[..at].chars().next_back()
+    }
+
+    fn next(&self, at: usize) -> Option<char> {
+        self.text[at..].chars().next()
+    }
+}
+
+// the first Repeat frame of a quantifier
+fn repetition(quantifier: &AST, greedy: bool, instance: usize, flags: Flags) -> Frame<'_> {
+    let (body, min, max) = match quantifier {
+        AST::Closure(body) => (body, 0, None),
+        AST::OneOrMore(body) => (body, 1, None),
+        AST::Optional(body) => (body, 0, Some(1)),
+        AST::Repeat { ast, min, max } => (ast, *min, *max),
+        ast => return Frame::Node(ast, instance, flags),
+    };
+    Frame::Repeat {
+        quantifier,
+        body,
+        min,
+        max,
+        greedy,
+        done: 0,
+        instance,
+        flags,
+    }
+}
+
+// the fewest and most chars the AST can match, None if there is no most
+fn width(ast: &AST) -> (usize, Option<usize>) {
+    // the width of the node visited last; groups, lazy and flags keep their inside's
+    struct Width(usize, Option<usize>);
+
+    impl Width {
+        fn of(&mut self, ast: &AST) -> (usize, Option<usize>) {
+            self.visit(ast);
+            (self.0, self.1)
+        }
+    }
+
+    impl Visitor for Width {
+        fn visit_char(&mut self, c: char) {
+            *self = Width(1, Some(1));
+        }
+
+        fn visit_any_char(&mut self) {
+            *self = Width(1, Some(1));
+        }
+
+        fn visit_class(&mut self, class: &CharClass) {
+            *self = Width(1, Some(1));
+        }
+
+        fn visit_empty(&mut self) {
+            *self = Width(0, Some(0));
+        }
+
+        fn visit_assertion(&mut self, assertion: Assertion) {
+            *self = Width(0, Some(0));
+        }
+
+        fn visit_lookaround(&mut self, kind: Lookaround, ast: &AST) {
+            *self = Width(0, Some(0));
+        }
+
+        fn visit_backref(&mut self, index: usize) {
+            *self = Width(0, None);
+        }
+
+        fn visit_catenation(&mut self, lhs: &AST, rhs: &AST) {
+            let ((lmin, lmax), (rmin, rmax)) = (self.of(lhs), self.of(rhs));
+            *self = Width(lmin + rmin, lmax.zip(rmax).map(|(l, r)| l + r));
+        }
+
+        fn visit_alternation(&mut self, lhs: &AST, rhs: &AST) {
+            let ((lmin, lmax), (rmin, rmax)) = (self.of(lhs), self.of(rhs));
+            *self = Width(lmin.min(rmin), lmax.zip(rmax).map(|(l, r)| l.max(r)));
+        }
+
+        fn visit_closure(&mut self, ast: &AST) {
+            *self = Width(0, None);
+        }
+
+        fn visit_one_or_more(&mut self, ast: &AST) {
+            *self = Width(self.of(ast).0, None);
+        }
+
+        fn visit_optional(&mut self, ast: &AST) {
+            *self = Width(0, self.of(ast).1);
+        }
+
+        fn visit_repeat(&mut self, ast: &AST, min: usize, max: Option<usize>) {
+            let (amin, amax) = self.of(ast);
+            let most = match (amax, max) {
+                (Some(0), _) => Some(0),
+                (Some(amax), Some(max)) => amax.checked_mul(max),
+                _ => None,
+            };
+            *self = Width(amin.saturating_mul(min), most);
+        }
+    }
+
+    Width(0, Some(0)).of(ast)
+}
+
+// lookbehind is tried from each start its length allows, so that length must be bounded
+fn check_lookbehinds(ast: &AST) -> Result<(), Error> {
+    struct Bounded(bool);
+
+    impl Visitor for Bounded {
+        fn visit_lookaround(&mut self, kind: Lookaround, ast: &AST) {
+            if let Lookaround::Behind | Lookaround::NotBehind = kind {
+                self.0 &= width(ast).1.is_some();
+            }
+            self.visit(ast);
+        }
+    }
+
+    let mut bounded = Bounded(true);
+    bounded.visit(ast);
+    if bounded.0 {
+        Ok(())
+    } else {
+        Err(Error::Unsupported(String::from(
+            "Lookbehind needs a bounded length, it can't contain *, +, {n,} or backreferences",
+        )))
+    }
+}
+
+// for the nodes that match exactly one char; with case ignored, c matches
+// if any of its case variants does, which is what the NFA's folded classes do
+fn char_matches(ast: &AST, c: char, flags: Flags) -> bool {
+    if flags.case_insensitive {
+        if let AST::Char(_) | AST::Class(_) = ast {
+            let variants = unicode::case_variants(c);
+            return match ast {
+                AST::Class(class) if class.is_negated() => variants.iter().all(|&v| class.contains(v)),
+                _ => variants.iter().any(|&v| char_matches(ast, v, Flags::default())),
+            };
+        }
+    }
+    match ast {
+        AST::Char(expected) => *expected == c,
+        AST::AnyChar => flags.dot_matches_newline || c != '\n',
+        AST::Class(class) => class.contains(c),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod backtrack {
+    use super::*;
+
+    #[test]
+    fn repeated_words() {
+        let bt = Backtrack::from("\\b(\\w+) \\1\\b").unwrap();
+        assert_eq!(bt.is_match("it was the the best"), Ok(true));
+        assert_eq!(bt.is_match("it was the best"), Ok(false));
+        assert_eq!(bt.is_match("the theory"), Ok(false));
+        let caps = bt.captures("so so").unwrap().unwrap();
+        assert_eq!(caps.get(0), Some("so so"));
+        assert_eq!(caps.get(1), Some("so"));
+    }
+
+    #[test]
+    fn unset_group() {
+        let bt = Backtrack::from("(a)?b\\1").unwrap();
+        assert_eq!(bt.is_match("b"), Ok(false));
+        assert_eq!(bt.is_match("aba"), Ok(true));
+    }
+
+    #[test]
+    fn same_match_as_pike() {
+        // none of these have backreferences, so the two engines must agree
+        let patterns = [
+            "(a|ab)(c|bcd)",
+            "<(.+)>",
+            "<(.+?)>",
+            "x(a{2,4}?)",
+            "(\\w)+",
+            "(a*)*b",
+            "^(\\d+)-(\\d+)$",
+            "(?i)(A[B-C]+)",
+            "(?s:<.+>)",
+            "(?m)(^\\w+$)",
+            "(?:()|[ab]+)?",
+            "(?:()||[ab])+",
+            "((?:[ab]*?){1,})",
+            "(a*?)*",
+        ];
+        let texts = ["abcd", "<a><b>", "xaaaa", "abc", "aaab", "10-20", "", "<a\nb>", "ab\nc"];
+        for pattern in patterns.iter() {
+            let nfa = nfa::NFA::from(pattern).unwrap();
+            let bt = Backtrack::from(pattern).unwrap();
+            for text in texts.iter() {
+                assert_eq!(bt.captures(text).unwrap(), nfa.captures(text), "{} on {:?}", pattern, text);
+            }
+        }
+    }
+
+    #[test]
+    fn named_backref() {
+        let bt = Backtrack::from("(?P<quote>['\"]).*\\1").unwrap();
+        let caps = bt.captures("say 'hi' now").unwrap().unwrap();
+        assert_eq!(caps.name("quote"), Some("'"));
+        assert_eq!(caps.get(0), Some("'hi'"));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let config = Config {
+            case_insensitive: true,
+            ..Config::default()
+        };
+        let bt = Backtrack::with_config("(σ+) \\1", &config).unwrap();
+        assert_eq!(bt.is_match("ΣΣ σς"), Ok(true));
+        assert_eq!(bt.is_match("ΣΣ ΣΣ"), Ok(true));
+        assert_eq!(bt.is_match("σσ τ"), Ok(false));
+        let bt = Backtrack::from("(a) (?i:\\1)").unwrap();
+        assert_eq!(bt.is_match("a A"), Ok(true));
+        assert_eq!(bt.is_match("A a"), Ok(false));
+    }
+
+    #[test]
+    fn lookahead() {
+        let bt = Backtrack::from("password=(?!\\*\\*\\*)").unwrap();
+        assert_eq!(bt.is_match("password=hunter2"), Ok(true));
+        assert_eq!(bt.is_match("password=***"), Ok(false));
+        let bt = Backtrack::from("\\w+(?=(\\d))").unwrap();
+        let caps = bt.captures("abc1").unwrap().unwrap();
+        assert_eq!(caps.get(0), Some("abc"));
+        assert_eq!(caps.get(1), Some("1"));
+        let bt = Backtrack::from("a(?!(b))\\w").unwrap();
+        let caps = bt.captures("ab ac").unwrap().unwrap();
+        assert_eq!(caps.get(0), Some("ac"));
+        assert_eq!(caps.get(1), None);
+    }
+
+    // a lookahead at every start of a long line still fits the step budget
+    #[test]
+    fn lookahead_long_line() {
+        let bt = Backtrack::from("b(?=c)").unwrap();
+        assert_eq!(bt.is_match(&"b".repeat(600_000)), Ok(false));
+        assert_eq!(bt.is_match(&format!("{}c", "b".repeat(600_000))), Ok(true));
+    }
+
+    #[test]
+    fn lookbehind() {
+        let bt = Backtrack::from("(?<=\\$)\\d+").unwrap();
+        assert_eq!(bt.captures("costs $40").unwrap().unwrap().get(0), Some("40"));
+        assert_eq!(bt.is_match("costs 40"), Ok(false));
+        let bt = Backtrack::from("(?<!é|ab?)c").unwrap();
+        assert_eq!(bt.is_match("éc"), Ok(false));
+        assert_eq!(bt.is_match("abc"), Ok(false));
+        assert_eq!(bt.is_match("xbc"), Ok(true));
+        assert_eq!(bt.is_match("c"), Ok(true));
+        let bt = Backtrack::from("(?<=^a{2,3})b").unwrap();
+        assert_eq!(bt.is_match("aab"), Ok(true));
+        assert_eq!(bt.is_match("aaab"), Ok(true));
+        assert_eq!(bt.is_match("ab"), Ok(false));
+    }
+
+    #[test]
+    fn unbounded_lookbehind() {
+        assert!(Backtrack::from("(?<=a+)b").is_err());
+        assert!(Backtrack::from("(a)(?<!\\1)b").is_err());
+        assert!(Backtrack::from("x(y|(?:(?<!a{2,})c)*)").is_err());
+        assert!(Backtrack::from("(?<=a{0}(?=b+))b").is_ok());
+    }
+
+    #[test]
+    fn step_limit() {
+        let config = Config {
+            step_limit: 10_000,
+            ..Config::default()
+        };
+        let bt = Backtrack::with_config("(a+)+\\1b", &config).unwrap();
+        assert!(bt.is_match(&"a".repeat(30)).is_err());
+    }
+
+    // each start gets the whole budget, so a line with many starts is fine
+    #[test]
+    fn step_limit_per_start() {
+        let bt = Backtrack::from("(x)\\1").unwrap();
+        assert_eq!(bt.is_match(&"xy".repeat(300_000)), Ok(false));
+    }
+
+    #[test]
+    fn many_group_iterations() {
+        let bt = Backtrack::from("(a)*\\1").unwrap();
+        assert_eq!(bt.is_match(&"a".repeat(50_000)), Ok(true));
+    }
+
+    #[test]
+    fn long_input() {
+        let bt = Backtrack::from("(x)\\1.*y").unwrap();
+        let text = format!("xx{}y", "-".repeat(100_000));
+        assert_eq!(bt.is_match(&text), Ok(true));
+    }
+}
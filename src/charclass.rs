@@ -0,0 +1,231 @@
+/**
+ * Tar Heel egrep - character classes
+ *
+ * A CharClass is a sorted set of non-overlapping inclusive char ranges,
+ * plus a flag saying whether the set was negated with [^...].
+ */
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CharClass {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+impl CharClass {
+    pub fn new() -> CharClass {
+        CharClass {
+            ranges: vec![],
+            negated: false,
+        }
+    }
+
+    /**
+     * Build a class out of any list of ranges; they get sorted and merged.
+     */
+    pub fn from_ranges(ranges: &[(char, char)]) -> CharClass {
+        let mut class = CharClass::new();
+        class.push_ranges(ranges);
+        class
+    }
+
+    pub fn push_char(&mut self, c: char) {
+        self.push_range(c, c);
+    }
+
+    /**
+     * Add an inclusive range to the set, keeping ranges sorted and merged.
+     */
+    pub fn push_range(&mut self, lo: char, hi: char) {
+        self.ranges.push((lo, hi));
+        self.canonicalize();
+    }
+
+    pub fn push_ranges(&mut self, ranges: &[(char, char)]) {
+        self.ranges.extend_from_slice(ranges);
+        self.canonicalize();
+    }
+
+    /**
+     * Union in everything another class matches, e.g. a \D inside [...].
+     */
+    pub fn push_class(&mut self, other: &CharClass) {
+        self.push_ranges(&other.matching_ranges());
+    }
+
+    pub fn negate(&mut self) {
+        self.negated = !self.negated;
+    }
+
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    /**
+     * The ranges of the set as written, ignoring negation.
+     */
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+
+    /**
+     * The ranges of chars this class actually matches, with negation applied.
+     */
+    pub fn matching_ranges(&self) -> Vec<(char, char)> {
+        if !self.negated {
+            return self.ranges.clone();
+        }
+        let mut out = Vec::new();
+        let mut next = Some('\0');
+        for &(lo, hi) in &self.ranges {
+            if let Some(start) = next {
+                if start < lo {
+                    out.push((start, prev_char(lo)));
+                }
+            }
+            next = next_char(hi);
+        }
+        if let Some(start) = next {
+            out.push((start, std::char::MAX));
+        }
+        out
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        let found = self
+            .ranges
+            .binary_search_by(|&(lo, hi)| {
+                if hi < c {
+                    std::cmp::Ordering::Less
+                } else if lo > c {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok();
+        found != self.negated
+    }
+
+    fn canonicalize(&mut self) {
+        self.ranges.sort();
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(self.ranges.len());
+        for &(lo, hi) in &self.ranges {
+            if let Some(last) = merged.last_mut() {
+                // overlapping or adjacent ranges collapse into one
+                if next_char(last.1).is_none_or(|after| lo <= after) {
+                    if hi > last.1 {
+                        last.1 = hi;
+                    }
+                    continue;
+                }
+            }
+            merged.push((lo, hi));
+        }
+        self.ranges = merged;
+    }
+}
+
+impl Default for CharClass {
+    fn default() -> CharClass {
+        CharClass::new()
+    }
+}
+
+/**
+ * Prints the class back in bracket syntax, e.g. [^a-z0-9_]
+ */
+impl std::fmt::Display for CharClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "[")?;
+        if self.negated {
+            write!(f, "^")?;
+        }
+        for &(lo, hi) in &self.ranges {
+            write!(f, "{}", class_escape(lo))?;
+            if hi > lo {
+                if next_char(lo) != Some(hi) {
+                    write!(f, "-")?;
+                }
+                write!(f, "{}", class_escape(hi))?;
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+// chars that mean something inside brackets get a backslash
+fn class_escape(c: char) -> String {
+    match c {
+        ']' | '[' | '\\' | '^' | '-' => format!("\\{}", c),
+        c if c.is_control() => format!("\\u{{{:x}}}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+/**
+ * Step to the neighbouring scalar value, hopping over the surrogate gap.
+ */
+pub fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        std::char::MAX => None,
+        c => std::char::from_u32(c as u32 + 1),
+    }
+}
+
+pub fn prev_char(c: char) -> char {
+    match c {
+        '\u{E000}' => '\u{D7FF}',
+        c => std::char::from_u32(c as u32 - 1).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod char_class {
+    use super::*;
+
+    #[test]
+    fn merges_ranges() {
+        let class = CharClass::from_ranges(&[('d', 'f'), ('a', 'c'), ('x', 'x'), ('e', 'k')]);
+        assert_eq!(class.ranges(), &[('a', 'k'), ('x', 'x')]);
+    }
+
+    #[test]
+    fn contains() {
+        let class = CharClass::from_ranges(&[('a', 'z'), ('0', '9'), ('_', '_')]);
+        assert!(class.contains('q'));
+        assert!(class.contains('5'));
+        assert!(class.contains('_'));
+        assert!(!class.contains('A'));
+        assert!(!class.contains('-'));
+    }
+
+    #[test]
+    fn negated() {
+        let mut class = CharClass::from_ranges(&[('a', 'c')]);
+        class.negate();
+        assert!(!class.contains('b'));
+        assert!(class.contains('d'));
+        assert_eq!(
+            class.matching_ranges(),
+            vec![('\0', '`'), ('d', std::char::MAX)]
+        );
+    }
+
+    #[test]
+    fn union_negated() {
+        let mut other = CharClass::from_ranges(&[('\0', 'b'), ('d', std::char::MAX)]);
+        other.negate();
+        let mut class = CharClass::from_ranges(&[('x', 'z')]);
+        class.push_class(&other);
+        assert_eq!(class.ranges(), &[('c', 'c'), ('x', 'z')]);
+    }
+
+    #[test]
+    fn display() {
+        let mut class = CharClass::from_ranges(&[('a', 'z'), ('-', '-'), ('x', 'x'), ('0', '1')]);
+        assert_eq!(format!("{}", class), "[\\-01a-z]");
+        class.negate();
+        assert_eq!(format!("{}", class), "[^\\-01a-z]");
+    }
+}
@@ -0,0 +1,111 @@
+use super::parser::ParseError;
+use std::fmt;
+
+/*
+ * Tar Heel egrep - errors
+ *
+ * Everything that can go wrong turning a pattern into a matcher or
+ * running one. Each kind of error has a code that won't change between
+ * versions, so callers can branch on that instead of on the message.
+ */
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    /**
+     * The pattern isn't valid syntax. Every problem that was found is
+     * listed in the order they appear in the pattern, so this is never
+     * empty.
+     */
+    Syntax(Vec<ParseError>),
+    /**
+     * The pattern expands to more NFA states than the limit in Config.
+     */
+    SizeLimit { limit: usize },
+    /**
+     * The pattern uses something the chosen engine can't do, like
+     * backreferences in the NFA or unbounded lookbehind.
+     */
+    Unsupported(String),
+    /**
+     * The backtracking matcher took more steps than the limit in Config.
+     */
+    StepLimit { limit: usize },
+}
+
+impl Error {
+    /**
+     * Stable identifier for the kind of error.
+     */
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Syntax(_) => "syntax",
+            Error::SizeLimit { .. } => "size-limit",
+            Error::Unsupported(_) => "unsupported",
+            Error::StepLimit { .. } => "step-limit",
+        }
+    }
+
+    /**
+     * The message, and for syntax errors the pattern with a caret under
+     * where each one went wrong, see ParseError::render.
+     */
+    pub fn render(&self, pattern: &str) -> String {
+        match self {
+            Error::Syntax(errors) => {
+                let rendered: Vec<String> = errors.iter().map(|error| error.render(pattern)).collect();
+                rendered.join("\n")
+            }
+            error => error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Syntax(errors) => {
+                let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+                write!(f, "{}", messages.join("; "))
+            }
+            Error::SizeLimit { limit } => write!(f, "Pattern is too large: it expands to more than {} states", limit),
+            Error::Unsupported(message) => write!(f, "{}", message),
+            Error::StepLimit { limit } => write!(
+                f,
+                "Backtracking gave up after {} steps, the pattern is too ambiguous for this input",
+                limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+
+#[cfg(test)]
+mod error {
+    use super::*;
+    use crate::backtrack::Backtrack;
+    use crate::nfa::{Config, NFA};
+
+    #[test]
+    fn kinds() {
+        assert_eq!(NFA::from("a(").unwrap_err().code(), "syntax");
+        assert_eq!(NFA::from("(a)\\1").unwrap_err().code(), "unsupported");
+        assert_eq!(NFA::from("a{1000}{1000}").unwrap_err().code(), "size-limit");
+        assert_eq!(Backtrack::from("(?<=a*)b").unwrap_err().code(), "unsupported");
+        let config = Config {
+            step_limit: 100,
+            ..Config::default()
+        };
+        let backtrack = Backtrack::with_config("(a*)*\\1b", &config).unwrap();
+        assert_eq!(backtrack.is_match(&"a".repeat(20)), Err(Error::StepLimit { limit: 100 }));
+    }
+
+    #[test]
+    fn boxed() {
+        let error: Box<dyn std::error::Error> = Box::new(NFA::from("[z-a]").unwrap_err());
+        assert_eq!(error.to_string(), "Invalid range in bracket expression: z-a");
+        let error = NFA::from("a)(*)").unwrap_err();
+        assert_eq!(error.to_string(), "Expected end of input, found RParen; Unexpected token: KleeneStar");
+    }
+}
@@ -0,0 +1,27 @@
+#![allow(unused)]
+/*
+ * thegrep - Tar Heel egrep, as a library
+ *
+ * The modules behind the thegrep binary, so other programs can match
+ * with Matcher and pull fields out of its Captures, or walk and rewrite
+ * patterns the parser built. main.rs is only the command line on top.
+ */
+
+pub mod backtrack;
+pub mod charclass;
+pub mod error;
+pub mod literal;
+pub mod matcher;
+pub mod nfa;
+pub mod parser;
+pub mod simplify;
+pub mod string_gen;
+pub mod tokenizer;
+pub mod unicode;
+pub mod visit;
+
+pub use self::error::Error;
+pub use self::matcher::Matcher;
+pub use self::nfa::{Captures, Config, NFA};
+pub use self::parser::AST;
+pub use self::visit::{rebuild, walk, Fold, Visitor};
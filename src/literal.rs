@@ -0,0 +1,193 @@
+/*
+ * Tar Heel egrep - literal searcher
+ *
+ * A fixed string doesn't need an automaton at all. Boyer-Moore-Horspool
+ * compares the last byte of the window first and, when the window can't
+ * be a match, slides it as far as the last byte allows: past the whole
+ * needle when that byte isn't in it at all. Searching is done on UTF-8
+ * bytes, which is safe because an encoded char can only ever match at a
+ * char boundary.
+ *
+ * To ignore case, the needle and every haystack are folded first, each
+ * char replaced by the smallest of its case variants, so chars that match
+ * each other with (?i) fold to the same one. Folding can change how many
+ * bytes a char takes, so a folded haystack keeps where its chars came
+ * from to report the span in the original.
+ */
+
+use super::unicode;
+
+/**
+ * A fixed string ready to be searched for.
+ */
+#[derive(Debug)]
+pub struct Literal {
+    needle: Vec<u8>,
+    // how far the window may slide when it ends in a given byte, for all 256 of them
+    shift: Vec<usize>,
+    // the needle is folded, and haystacks have to be too
+    fold_case: bool,
+}
+
+impl Literal {
+    pub fn new(needle: &str) -> Literal {
+        Literal::build(needle.as_bytes().to_vec(), false)
+    }
+
+    /**
+     * A fixed string that matches whatever it matches with case ignored,
+     * following the same simple case folding as (?i).
+     */
+    pub fn ignoring_case(needle: &str) -> Literal {
+        let folded: String = needle.chars().map(fold).collect();
+        Literal::build(folded.into_bytes(), true)
+    }
+
+    fn build(needle: Vec<u8>, fold_case: bool) -> Literal {
+        let mut shift = vec![needle.len(); 256];
+        if let Some((_, init)) = needle.split_last() {
+            for (i, &byte) in init.iter().enumerate() {
+                shift[byte as usize] = init.len() - i;
+            }
+        }
+        Literal { needle, shift, fold_case }
+    }
+
+    /**
+     * Byte offset of the first occurrence of the needle in haystack. The
+     * empty needle is found at 0.
+     */
+    pub fn find(&self, haystack: &str) -> Option<usize> {
+        self.find_span(haystack).map(|(start, _)| start)
+    }
+
+    /**
+     * Start and end byte offsets of the first occurrence of the needle in
+     * haystack. With case ignored the match can be longer or shorter than
+     * the needle, ſ is two bytes where the s it matches is one.
+     */
+    pub fn find_span(&self, haystack: &str) -> Option<(usize, usize)> {
+        if !self.fold_case {
+            return self.search(haystack.as_bytes()).map(|start| (start, start + self.needle.len()));
+        }
+        // folding keeps ASCII ASCII, so offsets only need mapping back past it
+        if haystack.is_ascii() {
+            let folded = haystack.to_ascii_uppercase();
+            return self.search(folded.as_bytes()).map(|start| (start, start + self.needle.len()));
+        }
+        let mut folded = String::with_capacity(haystack.len());
+        // the offset in haystack of the char each byte of folded came from
+        let mut origin = Vec::with_capacity(haystack.len() + 1);
+        for (at, c) in haystack.char_indices() {
+            let c = fold(c);
+            folded.push(c);
+            origin.extend(std::iter::repeat_n(at, c.len_utf8()));
+        }
+        origin.push(haystack.len());
+        self.search(folded.as_bytes())
+            .map(|start| (origin[start], origin[start + self.needle.len()]))
+    }
+
+    fn search(&self, haystack: &[u8]) -> Option<usize> {
+        let (last, init) = match self.needle.split_last() {
+            Some(split) => split,
+            None => return Some(0),
+        };
+        let mut at = 0;
+        while at + self.needle.len() <= haystack.len() {
+            let end = haystack[at + init.len()];
+            if end == *last && haystack[at..at + init.len()] == *init {
+                return Some(at);
+            }
+            at += self.shift[end as usize];
+        }
+        None
+    }
+
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.find(haystack).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.needle.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.needle.is_empty()
+    }
+}
+
+// the smallest char that matches c when case is ignored; for ASCII that is
+// always the uppercase letter, even for k, which the Kelvin sign also matches
+fn fold(c: char) -> char {
+    if c.is_ascii() {
+        c.to_ascii_uppercase()
+    } else {
+        unicode::case_variants(c).into_iter().min().unwrap_or(c)
+    }
+}
+
+#[cfg(test)]
+mod literal {
+    use super::*;
+
+    #[test]
+    fn find() {
+        let literal = Literal::new("abcab");
+        assert_eq!(literal.find("abcabcab"), Some(0));
+        assert_eq!(literal.find("xxabcabd"), Some(2));
+        assert_eq!(literal.find("abcaxabcab"), Some(5));
+        assert_eq!(literal.find("abca"), None);
+        assert_eq!(literal.find("bcabcaabca"), None);
+        assert_eq!(Literal::new("").find("abc"), Some(0));
+        assert_eq!(Literal::new("a").find(""), None);
+        assert_eq!(Literal::new("a.*").find("abc a.* x"), Some(4));
+    }
+
+    #[test]
+    fn utf8() {
+        let literal = Literal::new("λx");
+        assert_eq!(literal.find("aλλx"), Some(3));
+        assert!(!literal.is_match("λ x"));
+        assert_eq!(Literal::new("é").find("e\u{301}é"), Some(3));
+    }
+
+    // every offset, checked against the obvious search
+    #[test]
+    fn same_as_naive() {
+        let haystack = "abaabbabababbbaaabababbabbaabbabaabab";
+        for start in 0..haystack.len() {
+            for end in start..=haystack.len().min(start + 6) {
+                let needle = &haystack[start..end];
+                assert_eq!(Literal::new(needle).find(haystack), haystack.find(needle), "{}", needle);
+                assert_eq!(Literal::new(needle).find(&haystack[start + 1..]), haystack[start + 1..].find(needle), "{}", needle);
+            }
+        }
+    }
+
+    #[test]
+    fn ignoring_case() {
+        let literal = Literal::ignoring_case("Ab.[");
+        assert_eq!(literal.find_span("xaB.[y"), Some((1, 5)));
+        assert!(!literal.is_match("ab.c"));
+        assert_eq!(Literal::ignoring_case("straße").find_span("λ STRAẞE"), Some((3, 11)));
+        assert_eq!(Literal::ignoring_case("ſk").find_span("é sK"), Some((3, 5)));
+        assert_eq!(Literal::ignoring_case("sk").find_span("é ſ\u{212A}!"), Some((3, 8)));
+        assert_eq!(Literal::ignoring_case("σας").find("ΣΑΣ"), Some(0));
+        assert!(!Literal::ignoring_case("ss").is_match("ß"));
+        assert_eq!(Literal::ignoring_case("").find_span("λ"), Some((0, 0)));
+    }
+
+    // every char against every char, checked against (?i) in the NFA
+    #[test]
+    fn ignoring_case_as_nfa() {
+        let chars = ['a', 'A', 'k', 'K', '\u{212A}', 's', 'ſ', 'ß', 'ẞ', 'σ', 'ς', 'Σ', 'é', 'É', '7', '.'];
+        for &c in &chars {
+            let nfa = crate::nfa::NFA::from(&format!("(?i){}", crate::parser::cha(c).to_pattern())).unwrap();
+            for &t in &chars {
+                let text = t.to_string();
+                assert_eq!(Literal::ignoring_case(&c.to_string()).is_match(&text), nfa.accepts(&text), "{} {}", c, t);
+            }
+        }
+    }
+}
@@ -14,9 +14,10 @@ extern crate structopt;
 const EXIT_OK: i32 = 0;
 const EXIT_ERR: i32 = 1;
 
-pub mod nfa;
-use self::nfa::helpers::nfa_dot;
-use self::nfa::NFA;
+use thegrep::error::Error;
+use thegrep::matcher::Matcher;
+use thegrep::nfa::helpers::nfa_dot;
+use thegrep::nfa::{Config, NFA};
 use std::io;
 use std::process;
 use structopt::StructOpt;
@@ -30,12 +31,34 @@ struct Options {
     #[structopt(short = "p", long = "parse")]
     /// Show Parsed AST
     parse: bool,
+    #[structopt(long = "simplify")]
+    /// Show the pattern after simplification
+    simplify: bool,
     #[structopt(short = "t", long = "tokens")]
     /// Show Tokens
     tokens: bool,
     #[structopt(short = "g", long = "gen")]
     /// Show n Acceptable Strings
     n: Option<usize>,
+    #[structopt(short = "i", long = "ignore-case")]
+    /// Ignore case distinctions in the pattern and input
+    ignore_case: bool,
+    #[structopt(short = "G", long = "basic-regexp", raw(overrides_with = r#""extended_regexp""#))]
+    /// Read the pattern as a POSIX basic regular expression, like grep -G
+    basic_regexp: bool,
+    #[structopt(short = "E", long = "extended-regexp", raw(overrides_with = r#""basic_regexp""#))]
+    /// Read the pattern as an extended regular expression, the default
+    extended_regexp: bool,
+    #[structopt(
+        short = "F",
+        long = "fixed-strings",
+        raw(conflicts_with_all = r#"&["dot", "parse", "simplify", "tokens", "n"]"#)
+    )]
+    /// Match the pattern as a plain string, with no special chars
+    fixed_strings: bool,
+    #[structopt(short = "x", long = "verbose")]
+    /// Ignore whitespace and # comments in the pattern, as with (?x)
+    verbose: bool,
 
     /// Regular Expression Pattern
     pattern: String,
@@ -44,12 +67,10 @@ struct Options {
     paths: Vec<String>,
 }
 
-pub mod tokenizer;
-use self::tokenizer::Tokenizer;
-pub mod parser;
-use self::parser::Parser;
-pub mod string_gen;
-use self::string_gen::StringGen;
+use thegrep::parser::{Parser, AST};
+use thegrep::simplify::simplify;
+use thegrep::string_gen::StringGen;
+use thegrep::tokenizer::{Dialect, Tokenizer};
 
 fn main() {
     let options = Options::from_args();
@@ -58,27 +79,33 @@ fn main() {
 }
 
 fn eval(input: &str, options: &Options) {
+    let config = Config {
+        case_insensitive: options.ignore_case,
+        verbose: options.verbose,
+        dialect: if options.basic_regexp { Dialect::Basic } else { Dialect::Extended },
+        fixed_strings: options.fixed_strings,
+        ..Config::default()
+    };
     if options.dot {
-        eval_dot(input);
+        eval_dot(input, &config);
     }
     if options.parse {
-        eval_parse(input);
+        eval_parse(input, &config);
+    }
+    if options.simplify {
+        eval_simplify(input, &config);
     }
     if options.tokens {
-        eval_tokens(input);
+        eval_tokens(input, &config);
     }
     if let Some(number) = options.n {
-        eval_gen(input, number);
+        eval_gen(input, number, &config);
     }
 
-    // modifying input to allow pattern matching within strings
-    let mut mod_input = String::from("(.*)");
-    mod_input.push_str(input);
-
-    let result = if options.paths.len() > 0 {
-        print_files(&mod_input, options)
+    let result = if !options.paths.is_empty() {
+        print_files(input, options, &config)
     } else {
-        print_stdin(&mod_input)
+        print_stdin(input, &config)
     };
 }
 
@@ -86,67 +113,94 @@ use std::fs::File;
 use std::io::BufRead;
 
 // for file input
-fn print_files(input: &str, options: &Options) -> io::Result<()> {
+fn print_files(input: &str, options: &Options, config: &Config) -> io::Result<()> {
     for path in options.paths.iter() {
         let file = File::open(path)?;
         let reader = io::BufReader::new(file);
-        print_output(input, reader)?;
+        print_output(input, reader, config)?;
     }
     Ok(())
 }
 
 // for user input
-fn print_stdin(input: &str) -> io::Result<()> {
+fn print_stdin(input: &str, config: &Config) -> io::Result<()> {
     let stdin = io::stdin();
     let reader = stdin.lock();
-    print_output(input, reader)
+    print_output(input, reader, config)
 }
 
 // generically printing from different sources with method below (borrowed from lecture 18 lol)
-fn print_output<R: BufRead>(input: &str, reader: R) -> io::Result<()> {
-    let nfa = NFA::from(input).unwrap();
+fn print_output<R: BufRead>(input: &str, reader: R, config: &Config) -> io::Result<()> {
+    let matcher = Matcher::with_config(input, config).unwrap_or_else(|error| report(input, &error));
     for line in reader.lines() {
         let line_in = &*line?;
-        if nfa.accepts(line_in) {
-            println!("{}", line_in);
+        match matcher.is_match(line_in) {
+            Ok(true) => println!("{}", line_in),
+            Ok(false) => {}
+            Err(error) => report(input, &error),
         }
     }
     Ok(())
 }
 
+// bad patterns are reported instead of panicking
+fn fail(error: &str) -> ! {
+    eprintln!("thegrep: {}", error);
+    process::exit(EXIT_ERR);
+}
+
+// syntax errors also show where in the pattern they are, one after another
+fn report(input: &str, error: &Error) -> ! {
+    if let Error::Syntax(errors) = error {
+        for error in errors {
+            eprintln!("thegrep: {}", error.render(input));
+        }
+        process::exit(EXIT_ERR);
+    }
+    fail(&error.to_string())
+}
+
+fn parse_or_fail(input: &str, config: &Config) -> AST {
+    Parser::parse_with_flags(config.tokenizer(input), config.flags()).unwrap_or_else(|error| report(input, &error))
+}
+
 // print helpers for each flag
-fn eval_tokens(input: &str) {
-    let mut tokens = Tokenizer::new(input);
-    while let Some(token) = tokens.next() {
+fn eval_tokens(input: &str, config: &Config) {
+    for token in config.tokenizer(input) {
         println!("{:?}", token);
     }
-    print!("\n");
+    println!();
 }
 
-fn eval_parse(input: &str) {
-    match Parser::parse(Tokenizer::new(input)) {
+fn eval_parse(input: &str, config: &Config) {
+    match Parser::parse_with_flags(config.tokenizer(input), config.flags()) {
         Ok(fine) => {
             println!("{:?}", fine);
         }
-        Err(error) => eprintln!("thegrep: {}", error),
+        Err(error) => report(input, &error),
     }
-    print!("\n");
+    println!();
+}
+
+fn eval_simplify(input: &str, config: &Config) {
+    println!("{}", simplify(&parse_or_fail(input, config)));
+    println!();
 }
 
-fn eval_dot(input: &str) {
-    let nfa = NFA::from(&input).unwrap();
+fn eval_dot(input: &str, config: &Config) {
+    let nfa = NFA::from_ast(&parse_or_fail(input, config), config).unwrap_or_else(|error| report(input, &error));
     println!("{}", nfa_dot(&nfa));
     std::process::exit(0);
 }
 
-fn eval_gen(input: &str, number: usize) {
-    match StringGen::generate(input, number) {
+fn eval_gen(input: &str, number: usize, config: &Config) {
+    match StringGen::with_config(input, number, config) {
         Ok(fine) => {
             for string in fine {
                 println!("{}", string);
             }
         }
-        Err(error) => eprintln!("thegrep: {}", error),
+        Err(error) => report(input, &error),
     }
     process::exit(EXIT_OK);
 }
@@ -0,0 +1,149 @@
+use super::backtrack::Backtrack;
+use super::error::Error;
+use super::literal::Literal;
+use super::nfa::{self, Captures, Config, NFA};
+use super::parser::{Parser, AST};
+
+/*
+ * Tar Heel egrep - matcher
+ *
+ * Picks the engine a pattern runs on. The NFA takes time linear in the
+ * input, so it is used unless the pattern has backreferences or
+ * lookaround, which only the backtracking matcher can handle. Fixed
+ * strings skip both and are found with a substring search.
+ */
+
+#[derive(Debug)]
+pub enum Matcher {
+    NFA(NFA),
+    Backtrack(Backtrack),
+    Literal(Literal),
+}
+
+// a Literal match has no groups to name
+const NO_NAMES: &[Option<String>] = &[None];
+
+impl Matcher {
+    /**
+     * Compile a pattern for whichever engine it needs.
+     */
+    pub fn new(regular_expression: &str) -> Result<Matcher, Error> {
+        Matcher::with_config(regular_expression, &Config::default())
+    }
+
+    /**
+     * Compile a pattern for whichever engine it needs with the given Config.
+     */
+    pub fn with_config(regular_expression: &str, config: &Config) -> Result<Matcher, Error> {
+        if config.fixed_strings {
+            return Matcher::fixed(regular_expression, config);
+        }
+        let ast = Parser::parse_with_flags(config.tokenizer(regular_expression), config.flags())?;
+        Matcher::from_ast(ast, config)
+    }
+
+    /**
+     * Compile an already parsed pattern for whichever engine it needs.
+     */
+    pub fn from_ast(ast: AST, config: &Config) -> Result<Matcher, Error> {
+        if nfa::needs_backtracking(&ast) {
+            Ok(Matcher::Backtrack(Backtrack::from_ast(ast, config)?))
+        } else {
+            Ok(Matcher::NFA(NFA::from_ast(&ast, config)?))
+        }
+    }
+
+    /**
+     * Match text exactly as it is written, as with grep -F.
+     */
+    pub fn fixed(text: &str, config: &Config) -> Result<Matcher, Error> {
+        if config.case_insensitive {
+            Ok(Matcher::Literal(Literal::ignoring_case(text)))
+        } else {
+            Ok(Matcher::Literal(Literal::new(text)))
+        }
+    }
+
+    /**
+     * Search mode: is there a match starting anywhere in the input? Only
+     * the backtracking matcher can fail, when it runs out of steps.
+     */
+    pub fn is_match(&self, text: &str) -> Result<bool, Error> {
+        match self {
+            Matcher::NFA(nfa) => Ok(nfa.is_match(text)),
+            Matcher::Backtrack(backtrack) => backtrack.is_match(text),
+            Matcher::Literal(literal) => Ok(literal.is_match(text)),
+        }
+    }
+
+    /**
+     * Find the leftmost match in text and the spans of all of its groups.
+     */
+    pub fn captures<'t>(&'t self, text: &'t str) -> Result<Option<Captures<'t>>, Error> {
+        match self {
+            Matcher::NFA(nfa) => Ok(nfa.captures(text)),
+            Matcher::Backtrack(backtrack) => backtrack.captures(text),
+            Matcher::Literal(literal) => Ok(literal.find_span(text).map(|(start, end)| {
+                Captures::new(text, vec![Some(start), Some(end)], NO_NAMES)
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod matcher {
+    use super::*;
+
+    #[test]
+    fn picks_engine() {
+        assert!(matches!(Matcher::new("(\\w+) \\w+"), Ok(Matcher::NFA(_))));
+        assert!(matches!(Matcher::new("(\\w+) \\1"), Ok(Matcher::Backtrack(_))));
+        assert!(matches!(Matcher::new("a(?!b)"), Ok(Matcher::Backtrack(_))));
+    }
+
+    #[test]
+    fn backrefs() {
+        let matcher = Matcher::new("(\\w+) \\1").unwrap();
+        assert_eq!(matcher.is_match("hello hello"), Ok(true));
+        assert_eq!(matcher.is_match("hello world"), Ok(false));
+        let caps = matcher.captures("a b b c").unwrap().unwrap();
+        assert_eq!(caps.get(1), Some("b"));
+    }
+
+    #[test]
+    fn fixed_strings() {
+        let config = Config {
+            fixed_strings: true,
+            ..Config::default()
+        };
+        let matcher = Matcher::with_config("a.(b)*", &config).unwrap();
+        assert!(matches!(matcher, Matcher::Literal(_)));
+        assert_eq!(matcher.is_match("xa.(b)*y"), Ok(true));
+        assert_eq!(matcher.is_match("ab"), Ok(false));
+        let caps = matcher.captures("λ a.(b)*").unwrap().unwrap();
+        assert_eq!((caps.len(), caps.span(0)), (1, Some((3, 9))));
+        assert!(Matcher::with_config("(", &config).is_ok());
+
+        let config = Config {
+            case_insensitive: true,
+            ..config
+        };
+        let matcher = Matcher::with_config("Ab.[", &config).unwrap();
+        assert!(matches!(matcher, Matcher::Literal(_)));
+        assert_eq!(matcher.is_match("xaB.[y"), Ok(true));
+        assert_eq!(matcher.is_match("ab.c"), Ok(false));
+        let matcher = Matcher::with_config("sK", &config).unwrap();
+        let caps = matcher.captures("é ſ\u{212A}!").unwrap().unwrap();
+        assert_eq!(caps.span(0), Some((3, 8)));
+
+        // far too deep for the regex pipeline, which is why it isn't used
+        let long = "Ab".repeat(25_000);
+        let matcher = Matcher::with_config(&long, &config).unwrap();
+        assert_eq!(matcher.is_match(&format!("x{}", long.to_lowercase())), Ok(true));
+    }
+
+    #[test]
+    fn nfa_rejects_backrefs() {
+        assert!(NFA::from("(a)\\1").is_err());
+    }
+}
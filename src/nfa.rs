@@ -1,4 +1,8 @@
+#![allow(clippy::empty_line_after_doc_comments)]
 pub mod helpers;
+mod pike;
+
+pub use self::pike::Captures;
 
 // Starter code for PS06 - thegrep
 //
@@ -11,9 +15,15 @@ pub mod helpers;
 //
 
 use self::State::*;
+use super::charclass::CharClass;
+use super::error::Error;
 use super::parser::Parser;
-use super::parser::AST;
-use super::tokenizer::Tokenizer;
+use super::parser::{Assertion, Flags, Lookaround, AST};
+use super::simplify::simplify;
+use super::unicode;
+use super::tokenizer::{Dialect, Tokenizer};
+use super::unicode::is_word_char;
+use super::visit::Visitor;
 use std::ops;
 
 /**
@@ -28,46 +38,50 @@ use std::ops;
 pub struct NFA {
     start: StateId,
     states: Vec<State>,
+    groups: usize,
+    names: Vec<Option<String>>,
+    // the inline flags in effect while gen_fragment is building a node
+    flags: Flags,
 }
 
 /**
  * Overloading the add operator for the nfa
  */
-
 impl ops::Add<NFA> for NFA {
     type Output = NFA;
 
     fn add(self, rhs: NFA) -> NFA {
         let mut nfa = NFA::new();
+        nfa.groups = self.groups.max(rhs.groups);
+        nfa.names = if self.groups >= rhs.groups { self.names } else { rhs.names };
+
+        // lhs end state is dropped and rhs start state is skipped, so every rhs state
+        // is pushed down by length of lhs - 2
+        let lhs_end = self.states.len() - 1;
+        let offset = lhs_end - 1;
+
+        // anything in lhs that led to its end state now leads to the first state of rhs
+        let rhs_first = match rhs.states[rhs.start] {
+            Start(Some(first_state)) => first_state + offset,
+            _ => lhs_end,
+        };
 
         // add all lhs states except end state
         for state in self.states {
             match state {
                 State::End => break,
-                _ => nfa.add_state(state),
+                _ => nfa.add_state(state.map_ids(|id| if id == lhs_end { rhs_first } else { id })),
             };
         }
-        
-        // each state in rhs should be pushed down by length of lhs - end state
-        let offset = nfa.states.len() - 1;
 
         // adding rhs to returned nfa with offset; skip rhs start state
         for state in rhs.states {
             match state {
-                State::Start(Some(first_state)) => continue,
-                State::Match(c, Some(next_state)) => {
-                    nfa.add_state(Match(c, Some(next_state+offset)));
-                },
-                State::Split(Some(lnext_state), Some(rnext_state)) => {
-                    nfa.add_state(Split(Some(lnext_state+offset), Some(rnext_state+offset)));
-                },
-                State::End => {
-                    nfa.add_state(End);
-                },
-                _ => {},
-            }
+                State::Start(_) => continue,
+                _ => nfa.add_state(state.map_ids(|id| id + offset)),
+            };
         }
-        
+
         nfa
     }
 }
@@ -135,22 +149,128 @@ mod add_op {
         assert!(sum.accepts("ab"));
         assert!(sum.accepts("aabb"));
     }
+
+    // every lhs state that led to its end, not just the last one, has to
+    // be pointed at the first state of rhs
+    #[test]
+    fn add_joins_every_exit() {
+        let sum = NFA::from("(?:a|bc)?").unwrap() + NFA::from("(x)|y").unwrap();
+        for text in ["ax", "bcy", "x", "y"].iter() {
+            assert!(sum.accepts(text), "{}", text);
+        }
+        assert!(!sum.accepts("a"));
+        assert!(!sum.accepts("bcz"));
+        assert_eq!(sum.captures("bcx").unwrap().span(1), Some((2, 3)));
+    }
+}
+
+/**
+ * Settings for turning a pattern into an NFA.
+ */
+#[derive(Debug, Clone)]
+pub struct Config {
+    /**
+     * Most states a pattern may expand to, so that counted repetition
+     * like a{1000}{1000} is an error instead of running out of memory.
+     */
+    pub size_limit: usize,
+    /**
+     * Most steps the backtracking matcher may take from any one start in
+     * the input before it gives up with an error, for patterns that need
+     * backreferences.
+     */
+    pub step_limit: usize,
+    /**
+     * Ignore case in the whole pattern, as if it began with (?i).
+     */
+    pub case_insensitive: bool,
+    /**
+     * Ignore unescaped whitespace and # comments in the whole pattern,
+     * as if it began with (?x).
+     */
+    pub verbose: bool,
+    /**
+     * The syntax the pattern is written in, egrep's unless set to BRE.
+     */
+    pub dialect: Dialect,
+    /**
+     * Match the pattern as it is written, with no special chars at all.
+     */
+    pub fixed_strings: bool,
+}
+
+pub const DEFAULT_SIZE_LIMIT: usize = 100_000;
+pub const DEFAULT_STEP_LIMIT: usize = 1_000_000;
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            size_limit: DEFAULT_SIZE_LIMIT,
+            step_limit: DEFAULT_STEP_LIMIT,
+            case_insensitive: false,
+            verbose: false,
+            dialect: Dialect::Extended,
+            fixed_strings: false,
+        }
+    }
+}
+
+impl Config {
+    /**
+     * The flags a pattern is parsed with before any inline ones.
+     */
+    pub fn flags(&self) -> Flags {
+        Flags {
+            case_insensitive: self.case_insensitive,
+            ..Flags::default()
+        }
+    }
+
+    /**
+     * The tokens of a pattern in the configured dialect, read in verbose
+     * mode if verbose is set.
+     */
+    pub fn tokenizer<'p>(&self, pattern: &'p str) -> Tokenizer<'p> {
+        Tokenizer::with_dialect(pattern, self.dialect, self.verbose)
+    }
 }
 
 impl NFA {
     /**
      * Construct an NFA from a regular expression pattern.
      */
-    pub fn from(regular_expression: &str) -> Result<NFA, String> {
-        let mut nfa = NFA::new();
+    pub fn from(regular_expression: &str) -> Result<NFA, Error> {
+        NFA::with_config(regular_expression, &Config::default())
+    }
+
+    /**
+     * Construct an NFA from a regular expression pattern with the given Config.
+     */
+    pub fn with_config(regular_expression: &str, config: &Config) -> Result<NFA, Error> {
+        // Parse the Abstract Syntax Tree of the Regular Expression
+        let ast = Parser::parse_with_flags(config.tokenizer(regular_expression), config.flags())?;
+        NFA::from_ast(&ast, config)
+    }
 
+    /**
+     * Construct an NFA from an already parsed pattern, simplified first
+     * so it needs fewer states. Backreferences and lookaround can't be
+     * expressed by this automaton and are Unsupported errors, see
+     * backtrack::Backtrack.
+     */
+    pub fn from_ast(ast: &AST, config: &Config) -> Result<NFA, Error> {
+        let ast = &simplify(ast);
+        check_size(ast, config.size_limit)?;
+
+        let mut nfa = NFA::new();
         let start = nfa.add_state(Start(None));
         nfa.start = start;
+        nfa.groups = group_count(ast);
+        nfa.names = vec![None; nfa.groups + 1];
+        group_names(ast, &mut nfa.names);
 
-        // Parse the Abstract Syntax Tree of the Regular Expression
-        let ast = &Parser::parse(Tokenizer::new(regular_expression))?;
         // The "body" of the NFA is made of the states between Start and End
-        let body = nfa.gen_fragment(ast);
+        let body = nfa.gen_fragment(ast)?;
         nfa.join(nfa.start, body.start);
 
         let end = nfa.add_state(End);
@@ -161,80 +281,265 @@ impl NFA {
 
     /**
      * Given an input string, simulate the NFA to determine if the
-     * input is accepted by the input string.
+     * input is accepted by the input string. The match has to begin
+     * at the start of input but may end anywhere in it.
      */
     pub fn accepts(&self, input: &str) -> bool {
-        let mut chars = input.chars();
+        self.simulate(input, true)
+    }
 
-        // initialize current set of states with start state then step to 1st state
-        let mut c_states = vec![self.start];
-        let mut n_states = self.nstate_gen(None, c_states);
+    /**
+     * Search mode: is there a match starting anywhere in the input?
+     * This is what grep wants for each line.
+     */
+    pub fn is_match(&self, input: &str) -> bool {
+        self.simulate(input, false)
+    }
 
-        // this is where main computation happens
-        // (its an iterative solution with a mild sprinkling of recursion)
-        if !input.is_empty() {
-            while let Some(c) = chars.next() {
-                n_states = self.nstate_gen(Some(c), n_states);
-            }
-        } else {
-            n_states = self.nstate_gen(None, n_states);
-        }
+    /**
+     * Run the states in lockstep over input. When unanchored, the start
+     * state is fed back in at every position so a match can begin anywhere.
+     */
+    fn simulate(&self, input: &str, anchored: bool) -> bool {
+        let mut chars = input.chars().peekable();
 
-        // acts as a '.*' at the end of a regex
-        n_states = self.nstate_gen(None, n_states);
+        // initialize current set of states with everything reachable from start
+        let mut c_states = self.closure(vec![self.start], None, chars.peek().copied());
 
-        // checks if there is an end state in resulting current states
-        for id in n_states {
-            match self.states[id] {
-                State::End => return true,
-                _ => continue,
+        while let Some(c) = chars.next() {
+            // reaching the end early acts as a '.*' at the end of a regex
+            if self.has_end(&c_states) {
+                return true;
             }
+            let mut n_states = self.nstate_gen(c, &c_states);
+            if !anchored {
+                n_states.push(self.start);
+            } else if n_states.is_empty() {
+                return false;
+            }
+            c_states = self.closure(n_states, Some(c), chars.peek().copied());
         }
-        false
+        self.has_end(&c_states)
     }
 
-    fn nstate_gen(&self, input_char: Option<char>, c_states: Vec<StateId>) -> Vec<StateId> {
+    /**
+     * Step every Match state in c_states over input_char and return the
+     * states they land on.
+     */
+    fn nstate_gen(&self, input_char: char, c_states: &[StateId]) -> Vec<StateId> {
         let mut n_states = Vec::new();
-
-        for current in c_states {
-            match &self.states[current] {
-                State::Start(Some(first_state)) => n_states.push(*first_state),
-                State::Match(char_enum, Some(next_state)) => {
-                    // only run match state arm if there is an input char
-                    if let Some(character) = input_char {
-                        match *char_enum {
-                            Char::Literal(c) => {
-                                if character == c {
-                                    n_states.push(*next_state);
-                                }
-                            }
-                            Char::Any => n_states.push(*next_state),
-                        }
-                    }
+        for &current in c_states {
+            if let Match(char_enum, Some(next_state)) = &self.states[current] {
+                if char_enum.matches(input_char) {
+                    n_states.push(*next_state);
                 }
-                State::Split(Some(lnext_state), Some(rnext_state)) => {
-                    // if split state, test each split arm for matching the current char
-                    for state in self.nstate_gen(input_char, vec![*rnext_state]) {
-                        n_states.push(state);
+            }
+        }
+        n_states
+    }
+
+    /**
+     * Follow epsilon edges out of the given states, keeping only the states
+     * that wait on a char (Match) or accept (End). Each state is added once,
+     * which is what keeps loops like (a*)* from spinning forever.
+     * Assert states are only passed when their check holds between the
+     * chars before and after the current position.
+     */
+    fn closure(&self, from: Vec<StateId>, prev: Option<char>, next: Option<char>) -> Vec<StateId> {
+        let mut seen = vec![false; self.states.len()];
+        let mut out = Vec::new();
+        // explicit stack in reverse so lhs of a Split is explored first
+        let mut stack: Vec<StateId> = from.into_iter().rev().collect();
+        while let Some(id) = stack.pop() {
+            if seen[id] {
+                continue;
+            }
+            seen[id] = true;
+            match &self.states[id] {
+                Start(Some(next)) | Epsilon(Some(next)) | Save(_, Some(next)) => stack.push(*next),
+                Split(lhs, rhs) => {
+                    if let Some(rhs) = rhs {
+                        stack.push(*rhs);
                     }
-                    for state in self.nstate_gen(input_char, vec![*lnext_state]) {
-                        n_states.push(state);
+                    if let Some(lhs) = lhs {
+                        stack.push(*lhs);
                     }
                 }
-                _ => {
-                    // push current state if nothing matches because its a current state anyway
-                    n_states.push(current);
-                }
+                Assert(look, Some(next_state)) if look.holds(prev, next) => stack.push(*next_state),
+                Match(_, _) | End => out.push(id),
+                _ => {}
             }
         }
-        n_states
+        out
+    }
+
+    fn has_end(&self, states: &[StateId]) -> bool {
+        states.iter().any(|&id| matches!(self.states[id], End))
+    }
+}
+
+/**
+ * Count how many states an AST expands to, saturating instead of
+ * overflowing, and error out if that is more than the limit.
+ */
+pub fn check_size(ast: &AST, limit: usize) -> Result<(), Error> {
+    let size = expanded_size(ast);
+    if size > limit {
+        Err(Error::SizeLimit { limit })
+    } else {
+        Ok(())
+    }
+}
+
+/**
+ * Number of capture groups in an AST, which is its highest group index.
+ */
+pub(crate) fn group_count(ast: &AST) -> usize {
+    struct GroupCount(usize);
+
+    impl Visitor for GroupCount {
+        fn visit_group(&mut self, index: usize, name: Option<&str>, ast: &AST) {
+            self.0 = self.0.max(index);
+            self.visit(ast);
+        }
+    }
+
+    let mut count = GroupCount(0);
+    count.visit(ast);
+    count.0
+}
+
+/**
+ * Does the AST need the backtracking matcher anywhere, because it refers
+ * back to what a group matched or looks around the current position?
+ */
+pub fn needs_backtracking(ast: &AST) -> bool {
+    struct NeedsBacktracking(bool);
+
+    impl Visitor for NeedsBacktracking {
+        fn visit_backref(&mut self, index: usize) {
+            self.0 = true;
+        }
+
+        fn visit_lookaround(&mut self, kind: Lookaround, ast: &AST) {
+            self.0 = true;
+        }
+    }
+
+    let mut needs = NeedsBacktracking(false);
+    needs.visit(ast);
+    needs.0
+}
+
+/**
+ * Record the name of every named group at its index.
+ */
+pub(crate) fn group_names(ast: &AST, names: &mut [Option<String>]) {
+    struct GroupNames<'n>(&'n mut [Option<String>]);
+
+    impl Visitor for GroupNames<'_> {
+        fn visit_group(&mut self, index: usize, name: Option<&str>, ast: &AST) {
+            self.0[index] = name.map(String::from);
+            self.visit(ast);
+        }
+    }
+
+    GroupNames(names).visit(ast);
+}
+
+/**
+ * How many states the NFA for an AST would have, roughly: counted repetition
+ * is built from copies of what it repeats, so it counts once per copy.
+ */
+pub(crate) fn expanded_size(ast: &AST) -> usize {
+    struct ExpandedSize(usize);
+
+    impl ExpandedSize {
+        fn add(&mut self, states: usize) {
+            self.0 = self.0.saturating_add(states);
+        }
     }
+
+    impl Visitor for ExpandedSize {
+        fn visit_empty(&mut self) {
+            self.add(1);
+        }
+
+        fn visit_char(&mut self, c: char) {
+            self.add(1);
+        }
+
+        fn visit_any_char(&mut self) {
+            self.add(1);
+        }
+
+        fn visit_class(&mut self, class: &CharClass) {
+            self.add(1);
+        }
+
+        fn visit_assertion(&mut self, assertion: Assertion) {
+            self.add(1);
+        }
+
+        fn visit_backref(&mut self, index: usize) {
+            self.add(1);
+        }
+
+        fn visit_alternation(&mut self, lhs: &AST, rhs: &AST) {
+            self.visit(lhs);
+            self.visit(rhs);
+            self.add(1);
+        }
+
+        fn visit_closure(&mut self, ast: &AST) {
+            self.visit(ast);
+            self.add(1);
+        }
+
+        fn visit_one_or_more(&mut self, ast: &AST) {
+            self.visit(ast);
+            self.add(1);
+        }
+
+        fn visit_optional(&mut self, ast: &AST) {
+            self.visit(ast);
+            self.add(1);
+        }
+
+        fn visit_group(&mut self, index: usize, name: Option<&str>, ast: &AST) {
+            self.visit(ast);
+            self.add(2);
+        }
+
+        fn visit_lookaround(&mut self, kind: Lookaround, ast: &AST) {
+            self.visit(ast);
+            self.add(1);
+        }
+
+        fn visit_repeat(&mut self, ast: &AST, min: usize, max: Option<usize>) {
+            let size = expanded_size(ast);
+            let optional = match max {
+                Some(max) => max - min,
+                None => 1,
+            };
+            self.add(
+                size.saturating_mul(min)
+                    .saturating_add(size.saturating_add(1).saturating_mul(optional))
+                    .max(1),
+            );
+        }
+    }
+
+    let mut size = ExpandedSize(0);
+    size.visit(ast);
+    size.0
 }
 
 /*
  * Write Tests for Public API
  */
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod nfa_accepts {
     use super::*;
 
@@ -350,6 +655,297 @@ mod nfa_accepts {
         assert_eq!(nfa.accepts("ayyy"), true);
     }
 
+    #[test]
+    fn bracket_class() {
+        let nfa = NFA::from("v[0-9]+\\.[0-9]+").unwrap();
+        assert!(nfa.accepts("v1.10"));
+        assert!(nfa.accepts("v22.3-beta"));
+        assert!(!nfa.accepts("va.1"));
+        assert!(!nfa.accepts("v1x1"));
+    }
+
+    #[test]
+    fn negated_class() {
+        let nfa = NFA::from("[^aeiou][^aeiou]").unwrap();
+        assert!(nfa.accepts("xyz"));
+        assert!(nfa.accepts("--"));
+        assert!(!nfa.accepts("xa"));
+        assert!(!nfa.accepts("ax"));
+    }
+
+    #[test]
+    fn perl_classes() {
+        let nfa = NFA::from("\\w+=\\d+\\s*").unwrap();
+        assert!(nfa.accepts("retries=3"));
+        assert!(nfa.accepts("größe=٤٢ "));
+        assert!(!nfa.accepts("retries=x"));
+        let nfa = NFA::from("\\D\\S").unwrap();
+        assert!(nfa.accepts("a-"));
+        assert!(!nfa.accepts("1a"));
+        assert!(!nfa.accepts("a "));
+    }
+
+    #[test]
+    fn posix_classes() {
+        let nfa = NFA::from("[[:upper:]]+[[:punct:]][[:xdigit:]]").unwrap();
+        assert!(nfa.accepts("ERR!f"));
+        assert!(nfa.accepts("A.0"));
+        assert!(!nfa.accepts("Err!f"));
+        assert!(!nfa.accepts("ERR!g"));
+    }
+
+    #[test]
+    fn line_start() {
+        let nfa = NFA::from("^ERROR").unwrap();
+        assert!(nfa.is_match("ERROR: disk full"));
+        assert!(!nfa.is_match("no ERROR here"));
+    }
+
+    #[test]
+    fn line_end() {
+        let nfa = NFA::from("done$").unwrap();
+        assert!(nfa.is_match("all done"));
+        assert!(!nfa.is_match("done yet?"));
+        let nfa = NFA::from("^$").unwrap();
+        assert!(nfa.is_match(""));
+        assert!(!nfa.is_match(" "));
+    }
+
+    #[test]
+    fn anchors_in_alternation() {
+        let nfa = NFA::from("^a|b$").unwrap();
+        assert!(nfa.is_match("ax"));
+        assert!(nfa.is_match("xb"));
+        assert!(!nfa.is_match("xa"));
+        assert!(!nfa.is_match("bx"));
+    }
+
+    #[test]
+    fn case_insensitive_flag() {
+        let nfa = NFA::from("(?i)hello [a-c]+").unwrap();
+        assert!(nfa.is_match("HeLLo ABCabc"));
+        assert!(nfa.is_match("привет hello CAB"));
+        assert!(!nfa.is_match("hello d"));
+        let nfa = NFA::from("(?i)привет").unwrap();
+        assert!(nfa.is_match("ПРИВЕТ"));
+        let nfa = NFA::from("(?i)[^x]").unwrap();
+        assert!(!nfa.is_match("X"));
+    }
+
+    #[test]
+    fn case_insensitive_config() {
+        let config = Config {
+            case_insensitive: true,
+            ..Config::default()
+        };
+        let nfa = NFA::with_config("straße σ [a-c]", &config).unwrap();
+        assert!(nfa.is_match("STRAẞE Σ B"));
+        assert!(nfa.is_match("Straße ς c"));
+        assert!(!nfa.is_match("STRASSE Σ B"));
+        let nfa = NFA::with_config("(?-i:a)|k", &config).unwrap();
+        assert!(nfa.is_match("\u{212A}"));
+        assert!(!nfa.is_match("A"));
+    }
+
+    #[test]
+    fn verbose_config() {
+        let config = Config {
+            verbose: true,
+            ..Config::default()
+        };
+        let pattern = "^ (\\d{4}) - (\\d{2})   # year and month\n \\ \\# $";
+        let nfa = NFA::with_config(pattern, &config).unwrap();
+        assert!(nfa.is_match("2024-03 #"));
+        assert!(!nfa.is_match("2024 - 03 #"));
+        assert!(NFA::from("a b").unwrap().is_match("a b"));
+        assert!(!NFA::from("a\tb").unwrap().is_match("ab"));
+    }
+
+    #[test]
+    fn basic_config() {
+        let config = Config {
+            dialect: Dialect::Basic,
+            ..Config::default()
+        };
+        let nfa = NFA::with_config("\\(ab\\)*c\\|x\\{2\\}+?$", &config).unwrap();
+        assert!(nfa.accepts("ababc"));
+        assert!(nfa.accepts("xx+?"));
+        assert!(!nfa.accepts("xx"));
+        assert!(!nfa.accepts("ab"));
+        let nfa = NFA::with_config("*a(b|c)", &config).unwrap();
+        assert!(nfa.is_match("x*a(b|c)"));
+        assert!(!nfa.is_match("ab"));
+    }
+
+    #[test]
+    fn scoped_flags() {
+        let nfa = NFA::from("a(?i:b)c").unwrap();
+        assert!(nfa.is_match("aBc"));
+        assert!(!nfa.is_match("aBC"));
+        assert!(!nfa.is_match("ABc"));
+        let nfa = NFA::from("(?i)a(?-i)b|c").unwrap();
+        assert!(nfa.is_match("Ab"));
+        assert!(!nfa.is_match("AB"));
+        assert!(!nfa.is_match("C"));
+        let nfa = NFA::from("(?i)ab|c").unwrap();
+        assert!(nfa.is_match("C"));
+    }
+
+    #[test]
+    fn dot_and_newline() {
+        let nfa = NFA::from("a.b").unwrap();
+        assert!(!nfa.is_match("a\nb"));
+        let nfa = NFA::from("(?s)a.b").unwrap();
+        assert!(nfa.is_match("a\nb"));
+    }
+
+    #[test]
+    fn empty_alternatives() {
+        let nfa = NFA::from("^a(|b)c$").unwrap();
+        assert!(nfa.is_match("ac"));
+        assert!(nfa.is_match("abc"));
+        assert!(!nfa.is_match("abbc"));
+        let nfa = NFA::from("^(a|)$").unwrap();
+        assert!(nfa.is_match(""));
+        assert!(nfa.is_match("a"));
+        assert!(NFA::from("()").unwrap().is_match("anything"));
+        assert!(NFA::from("").unwrap().is_match(""));
+    }
+
+    #[test]
+    fn multi_line_anchors() {
+        let nfa = NFA::from("^b$").unwrap();
+        assert!(!nfa.is_match("a\nb\nc"));
+        let nfa = NFA::from("(?m)^b$").unwrap();
+        assert!(nfa.is_match("a\nb\nc"));
+        assert!(!nfa.is_match("a\nbb\nc"));
+    }
+
+    #[test]
+    fn word_boundary() {
+        let nfa = NFA::from("\\bid\\b").unwrap();
+        assert!(nfa.is_match("id"));
+        assert!(nfa.is_match("user id=4"));
+        assert!(nfa.is_match("(id)"));
+        assert!(!nfa.is_match("valid"));
+        assert!(!nfa.is_match("width"));
+        assert!(!nfa.is_match("idle"));
+    }
+
+    #[test]
+    fn unicode_word_boundary() {
+        let nfa = NFA::from("\\bstraße\\b").unwrap();
+        assert!(nfa.is_match("die straße ist"));
+        assert!(!nfa.is_match("hauptstraße"));
+    }
+
+    #[test]
+    fn not_word_boundary() {
+        let nfa = NFA::from("\\Bid\\B").unwrap();
+        assert!(nfa.is_match("width"));
+        assert!(!nfa.is_match("valid"));
+        assert!(!nfa.is_match("user id"));
+    }
+
+    #[test]
+    fn unanchored_search() {
+        let nfa = NFA::from("aut....a").unwrap();
+        assert!(nfa.is_match("Chautauqua"));
+        assert!(nfa.is_match("beautification's"));
+        assert!(!nfa.accepts("Chautauqua"));
+        assert!(!nfa.is_match("automat"));
+    }
+
+    #[test]
+    fn optional() {
+        let nfa = NFA::from("colou?r").unwrap();
+        assert!(nfa.accepts("color"));
+        assert!(nfa.accepts("colour"));
+        assert!(!nfa.accepts("colouur"));
+    }
+
+    #[test]
+    fn lazy_quantifiers() {
+        let nfa = NFA::from("<.+?>").unwrap();
+        assert!(nfa.accepts("<b>"));
+        assert!(!nfa.accepts("<>"));
+        let nfa = NFA::from("a*?b??c{1,2}?d").unwrap();
+        assert!(!nfa.accepts("d"));
+        assert!(nfa.accepts("cd"));
+        assert!(nfa.accepts("aabccd"));
+        assert!(!nfa.accepts("bbcd"));
+    }
+
+    #[test]
+    fn lazy_split_order() {
+        // the lazy closure's split prefers skipping ahead over looping
+        let nfa = NFA::from("a*?").unwrap();
+        let split = nfa.states.iter().find(|s| matches!(s, Split(_, _))).unwrap();
+        assert!(matches!(split, Split(Some(end), Some(_)) if matches!(nfa.states[*end], End)));
+    }
+
+    #[test]
+    fn exact_repeat() {
+        let nfa = NFA::from("id=[0-9]{3}!").unwrap();
+        assert!(nfa.accepts("id=123!"));
+        assert!(!nfa.accepts("id=12!"));
+        assert!(!nfa.accepts("id=1234!"));
+    }
+
+    #[test]
+    fn bounded_repeat() {
+        let nfa = NFA::from("x(ab){2,3}y").unwrap();
+        assert!(!nfa.accepts("xaby"));
+        assert!(nfa.accepts("xababy"));
+        assert!(nfa.accepts("xabababy"));
+        assert!(!nfa.accepts("xababababy"));
+    }
+
+    #[test]
+    fn unbounded_repeat() {
+        let nfa = NFA::from("a{2,}b").unwrap();
+        assert!(!nfa.accepts("ab"));
+        assert!(nfa.accepts("aab"));
+        assert!(nfa.accepts("aaaaaaab"));
+    }
+
+    #[test]
+    fn zero_repeat() {
+        let nfa = NFA::from("ab{0}c").unwrap();
+        assert!(nfa.accepts("ac"));
+        assert!(!nfa.accepts("abc"));
+    }
+
+    #[test]
+    fn nested_closures() {
+        let nfa = NFA::from("x(a*)*y").unwrap();
+        assert!(nfa.accepts("xy"));
+        assert!(nfa.accepts("xaaay"));
+        assert!(!nfa.accepts("xaaa"));
+    }
+
+    #[test]
+    fn size_limit() {
+        assert!(NFA::from("a{1000}").is_ok());
+        assert_eq!(
+            NFA::from("a{1000}{1000}").map_err(|e| e.to_string()).err(),
+            Some(String::from("Pattern is too large: it expands to more than 100000 states"))
+        );
+        let config = Config { size_limit: 10, ..Config::default() };
+        assert!(NFA::with_config("a{10}", &config).is_ok());
+        assert!(NFA::with_config("a{11}", &config).is_err());
+    }
+
+    #[test]
+    fn escaped_metachars() {
+        let nfa = NFA::from("10\\.0\\.0\\.1").unwrap();
+        assert!(nfa.accepts("10.0.0.1"));
+        assert!(!nfa.accepts("10a0b0c1"));
+        let nfa = NFA::from("v\\(1\\+\\)\\*").unwrap();
+        assert!(nfa.accepts("v(1+)*"));
+        assert!(!nfa.accepts("v1"));
+    }
+
 }
 
 /**
@@ -362,6 +958,9 @@ type StateId = usize;
  * - Start is starting state
  * - Match is a state with a single matching transition out
  * - Split is a state with two epsilon transitions out
+ * - Epsilon is a state with a single epsilon transition out
+ * - Assert is an epsilon transition that is only taken when its Look holds
+ * - Save is an epsilon transition that records the position into a capture slot
  * - End is the final accepting state
  */
 #[derive(Debug)]
@@ -369,9 +968,30 @@ enum State {
     Start(Option<StateId>),
     Match(Char, Option<StateId>),
     Split(Option<StateId>, Option<StateId>),
+    Epsilon(Option<StateId>),
+    Assert(Look, Option<StateId>),
+    Save(usize, Option<StateId>),
     End,
 }
 
+impl State {
+    /**
+     * Rewrite every outgoing StateId of this state, used when moving
+     * states from one NFA's arena into another.
+     */
+    fn map_ids<F: Fn(StateId) -> StateId>(self, f: F) -> State {
+        match self {
+            Start(next) => Start(next.map(&f)),
+            Match(c, next) => Match(c, next.map(&f)),
+            Split(lhs, rhs) => Split(lhs.map(&f), rhs.map(&f)),
+            Epsilon(next) => Epsilon(next.map(&f)),
+            Assert(look, next) => Assert(look, next.map(&f)),
+            Save(slot, next) => Save(slot, next.map(&f)),
+            End => End,
+        }
+    }
+}
+
 /**
  * Chars are the matching label of a non-epsilon edge in the
  * transition diagram representation of the NFA.
@@ -380,6 +1000,66 @@ enum State {
 enum Char {
     Literal(char),
     Any,
+    AnyButNewline,
+    Class(CharClass),
+}
+
+impl Char {
+    fn matches(&self, input_char: char) -> bool {
+        match self {
+            Char::Literal(c) => *c == input_char,
+            Char::Any => true,
+            Char::AnyButNewline => input_char != '\n',
+            Char::Class(class) => class.contains(input_char),
+        }
+    }
+}
+
+/**
+ * Looks are the zero-width checks of an Assert state, made by peeking
+ * at the chars on either side of the current position.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Look {
+    StartText,
+    EndText,
+    StartLine,
+    EndLine,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+impl Look {
+    /**
+     * The check an assertion makes under the given flags, where (?m)
+     * lets ^ and $ match next to a \n as well as at the ends.
+     */
+    pub(crate) fn new(assertion: Assertion, flags: Flags) -> Look {
+        match assertion {
+            Assertion::LineStart if flags.multi_line => Look::StartLine,
+            Assertion::LineEnd if flags.multi_line => Look::EndLine,
+            Assertion::LineStart => Look::StartText,
+            Assertion::LineEnd => Look::EndText,
+            Assertion::WordBoundary => Look::WordBoundary,
+            Assertion::NotWordBoundary => Look::NotWordBoundary,
+        }
+    }
+
+    pub(crate) fn holds(self, prev: Option<char>, next: Option<char>) -> bool {
+        match self {
+            Look::StartText => prev.is_none(),
+            Look::EndText => next.is_none(),
+            Look::StartLine => prev.is_none_or(|c| c == '\n'),
+            Look::EndLine => next.is_none_or(|c| c == '\n'),
+            Look::WordBoundary => is_word(prev) != is_word(next),
+            Look::NotWordBoundary => is_word(prev) == is_word(next),
+        }
+    }
+}
+
+// the edges of the input count as non-word chars
+fn is_word(c: Option<char>) -> bool {
+    c.is_some_and(is_word_char)
 }
 
 /**
@@ -404,6 +1084,9 @@ impl NFA {
         NFA {
             states: vec![],
             start: 0,
+            groups: 0,
+            names: vec![None],
+            flags: Flags::default(),
         }
     }
 
@@ -420,30 +1103,76 @@ impl NFA {
      * Given an AST node, this method returns a Fragment of the NFA
      * representing it and its children.
      */
-    fn gen_fragment(&mut self, ast: &AST) -> Fragment {
+    fn gen_fragment(&mut self, ast: &AST) -> Result<Fragment, Error> {
         match ast {
             AST::AnyChar => {
-                let state = self.add_state(Match(Char::Any, None));
-                Fragment {
+                let any = if self.flags.dot_matches_newline { Char::Any } else { Char::AnyButNewline };
+                let state = self.add_state(Match(any, None));
+                Ok(Fragment {
                     start: state,
                     ends: vec![state],
-                }
+                })
             }
             AST::Char(c) => {
-                let state = self.add_state(Match(Char::Literal(*c), None));
-                Fragment {
+                let variants = if self.flags.case_insensitive { unicode::case_variants(*c) } else { vec![*c] };
+                let char_enum = if variants.len() == 1 {
+                    Char::Literal(*c)
+                } else {
+                    let ranges: Vec<(char, char)> = variants.iter().map(|&v| (v, v)).collect();
+                    Char::Class(CharClass::from_ranges(&ranges))
+                };
+                let state = self.add_state(Match(char_enum, None));
+                Ok(Fragment {
                     start: state,
                     ends: vec![state],
-                }
+                })
+            }
+            AST::Class(class) => {
+                let class = if self.flags.case_insensitive { unicode::case_fold(class) } else { class.clone() };
+                let state = self.add_state(Match(Char::Class(class), None));
+                Ok(Fragment {
+                    start: state,
+                    ends: vec![state],
+                })
+            }
+            AST::Assertion(assertion) => {
+                let state = self.add_state(Assert(Look::new(*assertion, self.flags), None));
+                Ok(Fragment {
+                    start: state,
+                    ends: vec![state],
+                })
+            }
+            AST::Empty => Ok(self.epsilon()),
+            AST::Group { index, ast, .. } => self.group_helper(*index, ast),
+            AST::Flags(flags, ast) => {
+                let outer = self.flags;
+                self.flags = *flags;
+                let fragment = self.gen_fragment(ast);
+                self.flags = outer;
+                fragment
             }
             AST::Catenation(lhs, rhs) => self.cat_helper(lhs, rhs),
             AST::Alternation(lhs, rhs) => {
                 let ends = Vec::new();
                 self.alt_helper(lhs, rhs, ends)
             }
-            AST::Closure(ast) => self.clo_helper(ast),
-            AST::OneOrMore(ast) => self.plus_helper(ast),
-            node => panic!("Unimplemented branch of gen_fragment: {:?}", node),
+            AST::Closure(ast) => self.clo_helper(ast, true),
+            AST::OneOrMore(ast) => self.plus_helper(ast, true),
+            AST::Optional(ast) => self.opt_helper(ast, true),
+            AST::Repeat { ast, min, max } => self.repeat_helper(ast, *min, *max, true),
+            AST::Lazy(quantified) => match &**quantified {
+                AST::Closure(ast) => self.clo_helper(ast, false),
+                AST::OneOrMore(ast) => self.plus_helper(ast, false),
+                AST::Optional(ast) => self.opt_helper(ast, false),
+                AST::Repeat { ast, min, max } => self.repeat_helper(ast, *min, *max, false),
+                ast => self.gen_fragment(ast),
+            },
+            AST::Backref(_) => Err(Error::Unsupported(String::from(
+                "Backreferences are not supported by the NFA, use the backtracking matcher",
+            ))),
+            AST::Lookaround(_, _) => Err(Error::Unsupported(String::from(
+                "Lookaround is not supported by the NFA, use the backtracking matcher",
+            ))),
         }
     }
 
@@ -458,79 +1187,168 @@ impl NFA {
 
     /**
      * Join a loose end of one state to another by IDs.
-     * Note in the Split case, only the 2nd ID (rhs) is being bound.
-     * It is assumed when building an NFA with these constructs
-     * that the lhs of an Split state will always be known and bound.
+     * Note in the Split case, only the side that is still None is being
+     * bound; the other side is always known when the Split is created.
+     * Greedy Splits leave the rhs loose and lazy Splits leave the lhs loose.
      */
     fn join(&mut self, from: StateId, to: StateId) {
         match self.states[from] {
             Start(ref mut next) => *next = Some(to),
             Match(_, ref mut next) => *next = Some(to),
+            Split(ref mut lhs @ None, _) => *lhs = Some(to),
             Split(_, ref mut next) => *next = Some(to),
+            Epsilon(ref mut next) => *next = Some(to),
+            Assert(_, ref mut next) => *next = Some(to),
+            Save(_, ref mut next) => *next = Some(to),
             End => {}
         }
     }
 
+    /**
+     * A fragment that matches the empty string.
+     */
+    fn epsilon(&mut self) -> Fragment {
+        let state = self.add_state(Epsilon(None));
+        Fragment {
+            start: state,
+            ends: vec![state],
+        }
+    }
+
+    /**
+     * Join two already built fragments one after the other.
+     */
+    fn chain(&mut self, first: Fragment, second: Fragment) -> Fragment {
+        self.join_fragment(&first, second.start);
+        Fragment {
+            start: first.start,
+            ends: second.ends,
+        }
+    }
+
     /**
      * this is a helper function for catenation
      */
-    fn cat_helper(&mut self, lhs: &AST, rhs: &AST) -> Fragment {
-        let left = self.gen_fragment(lhs);
-        let right = self.gen_fragment(rhs);
+    fn cat_helper(&mut self, lhs: &AST, rhs: &AST) -> Result<Fragment, Error> {
+        let left = self.gen_fragment(lhs)?;
+        let right = self.gen_fragment(rhs)?;
         if right.start < self.states.len() {
             // leave last state unjoined so it can later be joined to end
             self.join_fragment(&left, right.start); // joining these two fragments together
         }
-        Fragment {
+        Ok(Fragment {
             //  creating fragment that has left's start and right's end
             start: left.start,
             ends: right.ends,
-        }
+        })
     }
 
     /**
      * helper for alternation
      */
-    fn alt_helper(&mut self, lhs: &AST, rhs: &AST, mut ends: Vec<StateId>) -> Fragment {
-        let left = self.gen_fragment(lhs);
+    fn alt_helper(&mut self, lhs: &AST, rhs: &AST, mut ends: Vec<StateId>) -> Result<Fragment, Error> {
+        let left = self.gen_fragment(lhs)?;
         for end in left.ends {
             // this is meant to "collect" those loose ends from the fragments
             ends.push(end);
         }
-        let right = self.gen_fragment(rhs);
+        let right = self.gen_fragment(rhs)?;
         for end in right.ends {
             ends.push(end);
         }
         let state = self.add_state(Split(Some(left.start), Some(right.start))); // create split state with left + right
-        Fragment {
+        Ok(Fragment {
             start: state,
-            ends: ends,
+            ends,
+        })
+    }
+
+    /**
+     * Split state that loops into / enters body. Simulation tries the lhs first,
+     * so a greedy Split puts body on the lhs and a lazy one puts it on the rhs.
+     */
+    fn split(&mut self, body: StateId, greedy: bool) -> StateId {
+        if greedy {
+            self.add_state(Split(Some(body), None))
+        } else {
+            self.add_state(Split(None, Some(body)))
         }
     }
 
     /**
      * attempting closure helper here (closure = split state + match state)
      */
-    fn clo_helper(&mut self, ast: &AST) -> Fragment {
-        let kleene_char = self.gen_fragment(ast); // generate fragment for the closure ast
-        let state = self.add_state(Split(Some(kleene_char.start), None)); // creating split state with match state at lhs
+    fn clo_helper(&mut self, ast: &AST, greedy: bool) -> Result<Fragment, Error> {
+        let kleene_char = self.gen_fragment(ast)?; // generate fragment for the closure ast
+        let state = self.split(kleene_char.start, greedy); // creating split state with match state on one side
         self.join_fragment(&kleene_char, state); // join closure ast and split state
-        Fragment {
+        Ok(Fragment {
             start: state,
             ends: vec![state],
-        }
+        })
     }
 
     /**
-     * one or more = match state + split state (lhs points back to match, rhs points forward)
+     * one or more = match state + split state (one side points back to match, the other points forward)
      */
-    fn plus_helper(&mut self, ast: &AST) -> Fragment {
-        let plus_char = self.gen_fragment(ast); // generating frag for oneormore ast
-        let state = self.add_state(Split(Some(plus_char.start), None)); // create split state with lhs pointing to match
+    fn plus_helper(&mut self, ast: &AST, greedy: bool) -> Result<Fragment, Error> {
+        let plus_char = self.gen_fragment(ast)?; // generating frag for oneormore ast
+        let state = self.split(plus_char.start, greedy); // create split state pointing back to match
         self.join_fragment(&plus_char, state);
-        Fragment { // unlike in closure, the start of this fragment is at the oneormore frag
+        Ok(Fragment { // unlike in closure, the start of this fragment is at the oneormore frag
             start: plus_char.start,
             ends: vec![state],
+        })
+    }
+
+    /**
+     * zero or one = split state where one side is the fragment and the other skips past it
+     */
+    fn opt_helper(&mut self, ast: &AST, greedy: bool) -> Result<Fragment, Error> {
+        let opt_frag = self.gen_fragment(ast)?;
+        let state = self.split(opt_frag.start, greedy);
+        let mut ends = opt_frag.ends;
+        ends.push(state);
+        Ok(Fragment { start: state, ends })
+    }
+
+    /**
+     * counted repetition is expanded into copies of the fragment: min required
+     * copies followed by either a closure (no max) or max - min optional copies
+     */
+    fn repeat_helper(&mut self, ast: &AST, min: usize, max: Option<usize>, greedy: bool) -> Result<Fragment, Error> {
+        let mut copies = Vec::new();
+        for _ in 0..min {
+            copies.push(self.gen_fragment(ast)?);
         }
+        match max {
+            None => copies.push(self.clo_helper(ast, greedy)?),
+            Some(max) => {
+                for _ in min..max {
+                    copies.push(self.opt_helper(ast, greedy)?);
+                }
+            }
+        }
+
+        let mut copies = copies.into_iter();
+        Ok(match copies.next() {
+            Some(first) => copies.fold(first, |frag, next| self.chain(frag, next)),
+            None => self.epsilon(), // x{0} only matches the empty string
+        })
+    }
+
+    /**
+     * capture group = save state for slot 2i, the group's fragment, then save state for slot 2i + 1
+     */
+    fn group_helper(&mut self, index: usize, ast: &AST) -> Result<Fragment, Error> {
+        let open = self.add_state(Save(2 * index, None));
+        let body = self.gen_fragment(ast)?;
+        let close = self.add_state(Save(2 * index + 1, None));
+        self.join(open, body.start);
+        self.join_fragment(&body, close);
+        Ok(Fragment {
+            start: open,
+            ends: vec![close],
+        })
     }
 }
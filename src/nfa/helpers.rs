@@ -1,5 +1,7 @@
+#![allow(clippy::empty_line_after_doc_comments)]
 use super::NFA;
 use super::Char;
+use super::Look;
 use super::State::*;
 
 /**
@@ -27,11 +29,28 @@ pub fn nfa_dot(nfa: &NFA) -> String {
     for (id, state) in nfa.states.iter().enumerate() {
         dot.push_str(&match state {
             Start(Some(next)) => format!("\tstart [shape=\"none\"]\n\tstart -> {}\n", next),
-            Match(c, Some(next)) => format!("\t{} -> {} [label=\"{}\"]\n", id, next, c),
+            Match(c, Some(next)) => format!(
+                "\t{} -> {} [label=\"{}\"]\n",
+                id,
+                next,
+                dot_escape(&c.to_string())
+            ),
             Split(Some(lhs), Some(rhs)) => format!(
                 "\t{0} -> {1} [label=\"ε\"]\n\t{0} -> {2} [label=\"ε\"]\n",
                 id, rhs, lhs
             ),
+            Epsilon(Some(next)) => format!("\t{} -> {} [label=\"ε\"]\n", id, next),
+            Assert(look, Some(next)) => format!("\t{} -> {} [label=\"{}\"]\n", id, next, look),
+            Save(slot, Some(next)) => format!(
+                "\t{} -> {} [label=\"{}{}\"]\n",
+                id,
+                next,
+                if slot % 2 == 0 { "(" } else { ")" },
+                match nfa.names.get(slot / 2) {
+                    Some(Some(name)) => dot_escape(name),
+                    _ => (slot / 2).to_string(),
+                }
+            ),
             End => format!("\t{} [shape=\"doublecircle\"]\n", id),
             _ => String::new(),
         });
@@ -48,6 +67,42 @@ impl std::fmt::Display for Char {
         match self {
             Char::Literal(c) => write!(f, "{}", c),
             Char::Any => write!(f, "ANY"),
+            Char::AnyButNewline => write!(f, "ANY-\\n"),
+            Char::Class(class) => write!(f, "{}", class),
+        }
+    }
+}
+
+/**
+ * Labels for the zero-width edges of Assert states.
+ */
+impl std::fmt::Display for Look {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Look::StartText => write!(f, "^"),
+            Look::EndText => write!(f, "$"),
+            Look::StartLine => write!(f, "(?m)^"),
+            Look::EndLine => write!(f, "(?m)$"),
+            Look::WordBoundary => write!(f, "\\b"),
+            Look::NotWordBoundary => write!(f, "\\B"),
+        }
+    }
+}
+
+/**
+ * Escape a label so it can sit inside a double quoted DOT string.
+ */
+fn dot_escape(label: &str) -> String {
+    let mut escaped = String::new();
+    for c in label.chars() {
+        match c {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if c.is_control() => escaped.push_str(&c.escape_default().to_string().replace('\\', "\\\\")),
+            c => escaped.push(c),
         }
     }
+    escaped
 }
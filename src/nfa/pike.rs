@@ -0,0 +1,334 @@
+use super::State::*;
+use super::{StateId, NFA};
+
+/*
+ * Pike VM simulation of the NFA for submatch extraction.
+ *
+ * Like accepts, every live state is stepped over the input in lockstep,
+ * but each thread also carries the byte offsets its Save states have
+ * recorded. Threads are kept in priority order (lhs of a Split first),
+ * which gives leftmost-first matches where greedy and lazy quantifiers
+ * behave the way they do in Perl.
+ */
+
+/**
+ * The spans of the capture groups of a match. Group 0 is the whole match.
+ */
+#[derive(Debug, PartialEq, Clone)]
+pub struct Captures<'t> {
+    text: &'t str,
+    slots: Vec<Option<usize>>,
+    names: &'t [Option<String>],
+}
+
+impl<'t> Captures<'t> {
+    pub(crate) fn new(text: &'t str, slots: Vec<Option<usize>>, names: &'t [Option<String>]) -> Captures<'t> {
+        Captures { text, slots, names }
+    }
+
+    /**
+     * Number of groups, including group 0.
+     */
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    /**
+     * Does the pattern have no groups besides group 0, the whole match?
+     * That group is always there, so this is len() == 1 rather than 0.
+     */
+    pub fn is_empty(&self) -> bool {
+        self.len() == 1
+    }
+
+    /**
+     * Byte span of group i, if it took part in the match.
+     */
+    pub fn span(&self, i: usize) -> Option<(usize, usize)> {
+        match (self.slots.get(2 * i)?, self.slots.get(2 * i + 1)?) {
+            (Some(start), Some(end)) => Some((*start, *end)),
+            _ => None,
+        }
+    }
+
+    /**
+     * The text that group i matched, if it took part in the match.
+     */
+    pub fn get(&self, i: usize) -> Option<&'t str> {
+        self.span(i).map(|(start, end)| &self.text[start..end])
+    }
+
+    /**
+     * Index of the group written (?P<name>...), if there is one.
+     */
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n.as_deref() == Some(name))
+    }
+
+    /**
+     * The text that the named group matched, if it took part in the match.
+     */
+    pub fn name(&self, name: &str) -> Option<&'t str> {
+        self.get(self.index_of(name)?)
+    }
+}
+
+impl NFA {
+    /**
+     * Find the leftmost match in text and the spans of all of its groups.
+     */
+    pub fn captures<'t>(&'t self, text: &'t str) -> Option<Captures<'t>> {
+        let nslots = 2 * (self.groups + 1);
+        let mut clist = Threads::new(self.states.len());
+        let mut nlist = Threads::new(self.states.len());
+        let mut matched = None;
+        let mut prev = None;
+        let mut at = 0;
+
+        loop {
+            let next = text[at..].chars().next();
+
+            // keep starting new threads, at the lowest priority, until something matches
+            if matched.is_none() {
+                let mut slots = vec![None; nslots];
+                slots[0] = Some(at);
+                self.add_thread(&mut clist, self.start, &mut slots, at, prev, next);
+            }
+            if clist.is_empty() && matched.is_some() {
+                break;
+            }
+
+            for (id, slots) in clist.threads.iter() {
+                match &self.states[*id] {
+                    Match(char_enum, Some(next_state)) => {
+                        if let Some(c) = next {
+                            if char_enum.matches(c) {
+                                let after = at + c.len_utf8();
+                                let mut slots = slots.clone();
+                                let lookahead = text[after..].chars().next();
+                                self.add_thread(&mut nlist, *next_state, &mut slots, after, Some(c), lookahead);
+                            }
+                        }
+                    }
+                    End => {
+                        let mut slots = slots.clone();
+                        slots[1] = Some(at);
+                        matched = Some(slots);
+                        // every thread after this one has a lower priority
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+
+            match next {
+                Some(c) => {
+                    std::mem::swap(&mut clist, &mut nlist);
+                    nlist.clear();
+                    prev = Some(c);
+                    at += c.len_utf8();
+                }
+                None => break,
+            }
+        }
+
+        matched.map(|slots| Captures::new(text, slots, &self.names))
+    }
+
+    /**
+     * Add the thread at id to list, following epsilon edges in priority
+     * order. Save states write the current offset into a slot for the rest
+     * of that path and then put the old value back.
+     */
+    fn add_thread(
+        &self,
+        list: &mut Threads,
+        id: StateId,
+        slots: &mut [Option<usize>],
+        at: usize,
+        prev: Option<char>,
+        next: Option<char>,
+    ) {
+        let mut stack = vec![Frame::Explore(id)];
+        while let Some(frame) = stack.pop() {
+            let id = match frame {
+                Frame::Explore(id) => id,
+                Frame::Restore(slot, old) => {
+                    slots[slot] = old;
+                    continue;
+                }
+            };
+            if !list.insert(id) {
+                continue;
+            }
+            match &self.states[id] {
+                Start(Some(next_state)) | Epsilon(Some(next_state)) => {
+                    stack.push(Frame::Explore(*next_state))
+                }
+                Split(lhs, rhs) => {
+                    if let Some(rhs) = rhs {
+                        stack.push(Frame::Explore(*rhs));
+                    }
+                    if let Some(lhs) = lhs {
+                        stack.push(Frame::Explore(*lhs));
+                    }
+                }
+                Assert(look, Some(next_state)) if look.holds(prev, next) => {
+                    stack.push(Frame::Explore(*next_state))
+                }
+                Save(slot, Some(next_state)) => {
+                    if *slot < slots.len() {
+                        stack.push(Frame::Restore(*slot, slots[*slot]));
+                        slots[*slot] = Some(at);
+                    }
+                    stack.push(Frame::Explore(*next_state));
+                }
+                Match(_, _) | End => list.threads.push((id, slots.to_vec())),
+                _ => {}
+            }
+        }
+    }
+}
+
+enum Frame {
+    Explore(StateId),
+    Restore(usize, Option<usize>),
+}
+
+/**
+ * Ordered set of threads, each state at most once per step.
+ */
+struct Threads {
+    seen: Vec<bool>,
+    visited: Vec<StateId>,
+    threads: Vec<(StateId, Vec<Option<usize>>)>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Threads {
+        Threads {
+            seen: vec![false; size],
+            visited: vec![],
+            threads: vec![],
+        }
+    }
+
+    // false if the state was already in the set
+    fn insert(&mut self, id: StateId) -> bool {
+        if self.seen[id] {
+            return false;
+        }
+        self.seen[id] = true;
+        self.visited.push(id);
+        true
+    }
+
+    fn is_empty(&self) -> bool {
+        self.threads.is_empty()
+    }
+
+    fn clear(&mut self) {
+        for id in self.visited.drain(..) {
+            self.seen[id] = false;
+        }
+        self.threads.clear();
+    }
+}
+
+#[cfg(test)]
+mod captures {
+    use super::*;
+
+    #[test]
+    fn whole_match() {
+        let nfa = NFA::from("b+").unwrap();
+        let caps = nfa.captures("aabbbc").unwrap();
+        assert_eq!(caps.span(0), Some((2, 5)));
+        assert_eq!(caps.get(0), Some("bbb"));
+        assert_eq!(caps.len(), 1);
+        assert!(caps.is_empty());
+        assert_eq!(nfa.captures("aaa"), None);
+    }
+
+    #[test]
+    fn groups() {
+        let nfa = NFA::from("(\\w+)=(\\d+)").unwrap();
+        let caps = nfa.captures("level=3 retries=10").unwrap();
+        assert_eq!(caps.get(0), Some("level=3"));
+        assert_eq!(caps.get(1), Some("level"));
+        assert_eq!(caps.get(2), Some("3"));
+        assert_eq!(caps.span(2), Some((6, 7)));
+    }
+
+    #[test]
+    fn unmatched_group() {
+        let nfa = NFA::from("a(x)?(b)").unwrap();
+        let caps = nfa.captures("ab").unwrap();
+        assert_eq!(caps.get(1), None);
+        assert_eq!(caps.get(2), Some("b"));
+        assert_eq!(caps.get(3), None);
+    }
+
+    #[test]
+    fn named_groups() {
+        let nfa = NFA::from("(?P<year>\\d{4})-(?:\\d{2})-(?P<day>\\d{2})").unwrap();
+        let caps = nfa.captures("on 2024-03-17").unwrap();
+        assert_eq!(caps.len(), 3);
+        assert!(!caps.is_empty());
+        assert_eq!(caps.name("year"), Some("2024"));
+        assert_eq!(caps.name("day"), Some("17"));
+        assert_eq!(caps.index_of("day"), Some(2));
+        assert_eq!(caps.get(2), Some("17"));
+        assert_eq!(caps.name("month"), None);
+    }
+
+    #[test]
+    fn leftmost_first() {
+        let nfa = NFA::from("(a|ab)(c|bcd)").unwrap();
+        let caps = nfa.captures("abcd").unwrap();
+        assert_eq!(caps.get(0), Some("abcd"));
+        assert_eq!(caps.get(1), Some("a"));
+        assert_eq!(caps.get(2), Some("bcd"));
+    }
+
+    #[test]
+    fn greedy_and_lazy() {
+        let greedy = NFA::from("<(.+)>").unwrap();
+        assert_eq!(greedy.captures("<a><b>").unwrap().get(1), Some("a><b"));
+        let lazy = NFA::from("<(.+?)>").unwrap();
+        assert_eq!(lazy.captures("<a><b>").unwrap().get(1), Some("a"));
+        let lazy = NFA::from("x(a{2,4}?)").unwrap();
+        assert_eq!(lazy.captures("xaaaa").unwrap().get(1), Some("aa"));
+    }
+
+    #[test]
+    fn last_iteration() {
+        let nfa = NFA::from("(\\w)+").unwrap();
+        assert_eq!(nfa.captures("abc").unwrap().get(1), Some("c"));
+    }
+
+    #[test]
+    fn byte_offsets() {
+        let nfa = NFA::from("(ß+)").unwrap();
+        let caps = nfa.captures("Straße").unwrap();
+        assert_eq!(caps.span(1), Some((4, 6)));
+        assert_eq!(caps.get(1), Some("ß"));
+    }
+
+    #[test]
+    fn assertion_after_start() {
+        let nfa = NFA::from("\\b(foo)$").unwrap();
+        let caps = nfa.captures("a foo").unwrap();
+        assert_eq!(caps.span(1), Some((2, 5)));
+        assert_eq!(nfa.captures("afoo"), None);
+    }
+
+    #[test]
+    fn anchored_groups() {
+        let nfa = NFA::from("^(\\d+)-(\\d+)$").unwrap();
+        let caps = nfa.captures("10-20").unwrap();
+        assert_eq!(caps.get(1), Some("10"));
+        assert_eq!(caps.get(2), Some("20"));
+        assert_eq!(nfa.captures("x10-20"), None);
+    }
+}
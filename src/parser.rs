@@ -1,4 +1,8 @@
-use super::tokenizer::{Token, Tokenizer};
+use super::charclass::CharClass;
+use super::error::Error;
+use super::tokenizer::{Span, Spanned, Token, Tokenizer};
+use super::unicode;
+use std::fmt;
 use std::iter::Peekable;
 
 /**
@@ -6,14 +10,88 @@ use std::iter::Peekable;
  *
  */
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum AST {
+    Empty,
     Alternation(Box<AST>, Box<AST>),
     Catenation(Box<AST>, Box<AST>),
     Closure(Box<AST>),
     OneOrMore(Box<AST>),
+    Optional(Box<AST>),
+    Lazy(Box<AST>),
     Char(char),
-    AnyChar
+    AnyChar,
+    Class(CharClass),
+    Assertion(Assertion),
+    Backref(usize),
+    Lookaround(Lookaround, Box<AST>),
+    Flags(Flags, Box<AST>),
+    Group {
+        index: usize,
+        name: Option<String>,
+        ast: Box<AST>,
+    },
+    Repeat {
+        ast: Box<AST>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+/**
+ * Zero-width checks on the position between two chars.
+ */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Assertion {
+    LineStart,
+    LineEnd,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+/**
+ * Zero-width checks that a sub-pattern does or doesn't match right
+ * after (ahead) or right before (behind) the current position.
+ */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Lookaround {
+    Ahead,
+    NotAhead,
+    Behind,
+    NotBehind,
+}
+
+/**
+ * The inline flags in effect for part of a pattern, set with (?ims) for
+ * the rest of the enclosing group or (?ims:...) for just that group.
+ * Verbose mode (?x) only changes tokenizing, so it isn't tracked here.
+ */
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Flags {
+    pub case_insensitive: bool,
+    pub dot_matches_newline: bool,
+    pub multi_line: bool,
+}
+
+impl Flags {
+    /**
+     * The flags after turning on the ones before a - in spec and turning
+     * off the ones after it, e.g. "i-s".
+     */
+    pub fn apply(self, spec: &str) -> Flags {
+        let mut flags = self;
+        let mut on = true;
+        for c in spec.chars() {
+            match c {
+                '-' => on = false,
+                'i' => flags.case_insensitive = on,
+                's' => flags.dot_matches_newline = on,
+                'm' => flags.multi_line = on,
+                _ => {}
+            }
+        }
+        flags
+    }
 }
 
 /* factory helper funcs because why not */
@@ -33,28 +111,485 @@ pub fn plus(val: AST) -> AST {
     AST::OneOrMore(Box::new(val))
 }
 
+pub fn opt(val: AST) -> AST {
+    AST::Optional(Box::new(val))
+}
+
+// wraps a closure, plus, optional or repeat so it prefers to match as little as it can
+pub fn lazy(val: AST) -> AST {
+    AST::Lazy(Box::new(val))
+}
+
+pub fn rep(val: AST, min: usize, max: Option<usize>) -> AST {
+    AST::Repeat {
+        ast: Box::new(val),
+        min,
+        max,
+    }
+}
+
+// capture group, numbered from 1 (group 0 is the whole match)
+pub fn grp(index: usize, val: AST) -> AST {
+    AST::Group {
+        index,
+        name: None,
+        ast: Box::new(val),
+    }
+}
+
+// capture group that can also be looked up by name, i.e. (?P<name>...)
+pub fn named(index: usize, name: &str, val: AST) -> AST {
+    AST::Group {
+        index,
+        name: Some(String::from(name)),
+        ast: Box::new(val),
+    }
+}
+
+// the flags inside val, which replace those outside of it
+pub fn flg(flags: Flags, val: AST) -> AST {
+    AST::Flags(flags, Box::new(val))
+}
+
 pub fn cha(c: char) -> AST {
     AST::Char(c)
 }
 
+pub fn cls(ranges: &[(char, char)], negated: bool) -> AST {
+    let mut class = CharClass::from_ranges(ranges);
+    if negated {
+        class.negate();
+    }
+    AST::Class(class)
+}
+
 // dont really need factory for AnyChar
 
+impl AST {
+    /**
+     * The pattern syntax for this AST, with parens only where the parser
+     * needs them. Parsing it gives back an equal AST for anything that
+     * Parser::parse could have produced.
+     */
+    pub fn to_pattern(&self) -> String {
+        self.to_string()
+    }
+
+    fn precedence(&self) -> Precedence {
+        match self {
+            // nothing at all only reads as Empty where a whole branch is expected
+            AST::Empty | AST::Alternation(_, _) => Precedence::Alternation,
+            AST::Catenation(_, _) => Precedence::Catenation,
+            AST::Closure(_) | AST::OneOrMore(_) | AST::Optional(_) | AST::Lazy(_) | AST::Repeat { .. } => Precedence::Quantified,
+            _ => Precedence::Atom,
+        }
+    }
+}
+
+/**
+ * Prints the AST back in pattern syntax, see AST::to_pattern.
+ */
+impl fmt::Display for AST {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_pattern(f, self, Flags::default(), Precedence::Alternation)
+    }
+}
+
+// how loosely each kind of node binds, lowest first
+#[derive(PartialEq, PartialOrd, Clone, Copy)]
+enum Precedence {
+    Alternation,
+    Catenation,
+    Quantified,
+    Atom,
+}
+
+// write ast where something binding at least as tightly as at is expected,
+// with flags being the ones in effect around it
+fn write_pattern(f: &mut fmt::Formatter, ast: &AST, flags: Flags, at: Precedence) -> fmt::Result {
+    if ast.precedence() < at {
+        write!(f, "(?:")?;
+        write_pattern(f, ast, flags, Precedence::Alternation)?;
+        return write!(f, ")");
+    }
+    match ast {
+        AST::Empty => Ok(()),
+        AST::Alternation(lhs, rhs) => {
+            // only a nested alternation on the left needs parens, an empty branch is just nothing
+            let at = if let AST::Alternation(_, _) = **lhs { Precedence::Catenation } else { Precedence::Alternation };
+            write_pattern(f, lhs, flags, at)?;
+            write!(f, "|")?;
+            write_pattern(f, rhs, flags, Precedence::Alternation)
+        }
+        AST::Catenation(lhs, rhs) => {
+            write_pattern(f, lhs, flags, Precedence::Quantified)?;
+            write_pattern(f, rhs, flags, Precedence::Catenation)
+        }
+        AST::Closure(ast) => {
+            write_pattern(f, ast, flags, Precedence::Quantified)?;
+            write!(f, "*")
+        }
+        AST::OneOrMore(ast) => {
+            write_pattern(f, ast, flags, Precedence::Quantified)?;
+            write!(f, "+")
+        }
+        AST::Optional(ast) => {
+            // a ? right after another quantifier would make that one lazy instead
+            let at = match **ast {
+                AST::Closure(_) | AST::OneOrMore(_) | AST::Optional(_) | AST::Repeat { .. } => Precedence::Atom,
+                _ => Precedence::Quantified,
+            };
+            write_pattern(f, ast, flags, at)?;
+            write!(f, "?")
+        }
+        AST::Lazy(ast) => {
+            write_pattern(f, ast, flags, Precedence::Quantified)?;
+            write!(f, "?")
+        }
+        AST::Repeat { ast, min, max } => {
+            write_pattern(f, ast, flags, Precedence::Quantified)?;
+            match max {
+                Some(max) if max == min => write!(f, "{{{}}}", min),
+                Some(max) => write!(f, "{{{},{}}}", min, max),
+                None => write!(f, "{{{},}}", min),
+            }
+        }
+        AST::Char(c) => write!(f, "{}", escape(*c)),
+        AST::AnyChar => write!(f, "."),
+        AST::Class(class) => match "dDsSwW".chars().find(|&c| unicode::perl_class(c).as_ref() == Some(class)) {
+            Some(c) => write!(f, "\\{}", c),
+            None => write!(f, "{}", class),
+        },
+        AST::Assertion(Assertion::LineStart) => write!(f, "^"),
+        AST::Assertion(Assertion::LineEnd) => write!(f, "$"),
+        AST::Assertion(Assertion::WordBoundary) => write!(f, "\\b"),
+        AST::Assertion(Assertion::NotWordBoundary) => write!(f, "\\B"),
+        AST::Backref(index) => write!(f, "\\{}", index),
+        AST::Lookaround(kind, ast) => {
+            match kind {
+                Lookaround::Ahead => write!(f, "(?=")?,
+                Lookaround::NotAhead => write!(f, "(?!")?,
+                Lookaround::Behind => write!(f, "(?<=")?,
+                Lookaround::NotBehind => write!(f, "(?<!")?,
+            }
+            write_pattern(f, ast, flags, Precedence::Alternation)?;
+            write!(f, ")")
+        }
+        AST::Flags(inner, ast) => {
+            write!(f, "(?{}:", flag_spec(flags, *inner))?;
+            write_pattern(f, ast, *inner, Precedence::Alternation)?;
+            write!(f, ")")
+        }
+        AST::Group { name, ast, .. } => {
+            match name {
+                Some(name) => write!(f, "(?P<{}>", name)?,
+                None => write!(f, "(")?,
+            }
+            write_pattern(f, ast, flags, Precedence::Alternation)?;
+            write!(f, ")")
+        }
+    }
+}
+
+// the (?spec:...) that turns outer into inner, e.g. "i-s"
+fn flag_spec(outer: Flags, inner: Flags) -> String {
+    let flags = [
+        ('i', outer.case_insensitive, inner.case_insensitive),
+        ('m', outer.multi_line, inner.multi_line),
+        ('s', outer.dot_matches_newline, inner.dot_matches_newline),
+    ];
+    let on: String = flags.iter().filter(|(_, outer, inner)| *inner && !outer).map(|(c, _, _)| c).collect();
+    let off: String = flags.iter().filter(|(_, outer, inner)| !inner && *outer).map(|(c, _, _)| c).collect();
+    if off.is_empty() {
+        on
+    } else {
+        format!("{}-{}", on, off)
+    }
+}
+
+// metachars get a backslash, and control chars are spelled out
+fn escape(c: char) -> String {
+    match c {
+        '\\' | '(' | ')' | '[' | '|' | '*' | '+' | '?' | '.' | '^' | '$' | '{' => format!("\\{}", c),
+        '\t' => String::from("\\t"),
+        '\n' => String::from("\\n"),
+        '\r' => String::from("\\r"),
+        c if c.is_control() => format!("\\u{{{:x}}}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod parser_display {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+
+    fn round_trip(pattern: &str) -> String {
+        Parser::parse(Tokenizer::new(pattern)).unwrap().to_pattern()
+    }
+
+    #[test]
+    fn minimal_parens() {
+        assert_eq!(round_trip("a|b|c"), "a|b|c");
+        assert_eq!(round_trip("ab(c|d)*e"), "ab(c|d)*e");
+        assert_eq!(round_trip("(?:ab)+|(?:)"), "(?:ab)+|");
+        assert_eq!(alt(alt(cha('a'), cha('b')), cha('c')).to_pattern(), "(?:a|b)|c");
+        assert_eq!(cat(cat(cha('a'), cha('b')), cha('c')).to_pattern(), "(?:ab)c");
+        assert_eq!(cat(alt(cha('a'), AST::Empty), clo(cat(cha('c'), cha('d')))).to_pattern(), "(?:a|)(?:cd)*");
+        assert_eq!(cat(AST::Empty, cha('a')).to_pattern(), "(?:)a");
+    }
+
+    #[test]
+    fn quantifiers() {
+        assert_eq!(round_trip("a*?b+c??d{2}e{2,}f{2,3}?"), "a*?b+c??d{2}e{2,}f{2,3}?");
+        assert_eq!(opt(clo(cha('a'))).to_pattern(), "(?:a*)?");
+        assert_eq!(opt(lazy(clo(cha('a')))).to_pattern(), "a*??");
+        assert_eq!(plus(clo(cha('a'))).to_pattern(), "a*+");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(round_trip("\\(\\.\\{x}\\\\\t\\u{1}\\d\\W[^a-c\\]]"), "\\(\\.\\{x}\\\\\\t\\u{1}\\d\\W[^\\]a-c]");
+        assert_eq!(round_trip("^\\bé\\B$"), "^\\bé\\B$");
+    }
+
+    #[test]
+    fn groups_and_flags() {
+        assert_eq!(round_trip("(?P<x>a)(b)\\2(?=c)(?!d)(?<=e)(?<!f)"), "(?P<x>a)(b)\\2(?=c)(?!d)(?<=e)(?<!f)");
+        assert_eq!(round_trip("(?i)a(?-i:b)|c"), "(?i:a(?-i:b))|(?i:c)");
+        assert_eq!(round_trip("(?ms:a(?i-s:b))"), "(?ms:a(?i-s:b))");
+    }
+
+    const CHARS: &[char] = &['a', 'b', 'Z', '0', '(', ')', '|', '*', '+', '?', '.', '[', ']', '{', '}', '^', '$', '\\', '-', '#', ' ', '\n', '\0', 'é', '😀'];
+
+    // a random AST of the shape the parser builds: groups numbered in the
+    // order they open, backrefs only to groups already opened, lazy only
+    // around a quantifier and flags only where they change something
+    fn random_ast(rng: &mut StdRng, groups: &mut usize, flags: Flags, depth: usize) -> AST {
+        let choice = if depth == 0 { rng.gen_range(0, 6) } else { rng.gen_range(0, 16) };
+        match choice {
+            0 => AST::Empty,
+            1 => cha(*CHARS.choose(rng).unwrap()),
+            2 => AST::AnyChar,
+            3 => random_class(rng),
+            4 => {
+                let assertions = [Assertion::LineStart, Assertion::LineEnd, Assertion::WordBoundary, Assertion::NotWordBoundary];
+                AST::Assertion(*assertions.choose(rng).unwrap())
+            }
+            5 if *groups > 0 => AST::Backref(rng.gen_range(1, *groups + 1)),
+            5 => cha('x'),
+            6 | 7 => {
+                let lhs = random_ast(rng, groups, flags, depth - 1);
+                alt(lhs, random_ast(rng, groups, flags, depth - 1))
+            }
+            8 | 9 => {
+                let lhs = random_ast(rng, groups, flags, depth - 1);
+                cat(lhs, random_ast(rng, groups, flags, depth - 1))
+            }
+            10 => {
+                let ast = random_ast(rng, groups, flags, depth - 1);
+                random_quantifier(rng, ast)
+            }
+            11 => {
+                let ast = random_ast(rng, groups, flags, depth - 1);
+                lazy(random_quantifier(rng, ast))
+            }
+            12 | 13 => {
+                *groups += 1;
+                let index = *groups;
+                let ast = random_ast(rng, groups, flags, depth - 1);
+                if rng.gen_bool(0.5) {
+                    grp(index, ast)
+                } else {
+                    named(index, &format!("g{}", index), ast)
+                }
+            }
+            14 => {
+                let kinds = [Lookaround::Ahead, Lookaround::NotAhead, Lookaround::Behind, Lookaround::NotBehind];
+                let kind = *kinds.choose(rng).unwrap();
+                AST::Lookaround(kind, Box::new(random_ast(rng, groups, flags, depth - 1)))
+            }
+            _ => {
+                let inner = loop {
+                    let inner = Flags {
+                        case_insensitive: rng.gen_bool(0.5),
+                        dot_matches_newline: rng.gen_bool(0.5),
+                        multi_line: rng.gen_bool(0.5),
+                    };
+                    if inner != flags {
+                        break inner;
+                    }
+                };
+                flg(inner, random_ast(rng, groups, inner, depth - 1))
+            }
+        }
+    }
+
+    fn random_quantifier(rng: &mut StdRng, ast: AST) -> AST {
+        match rng.gen_range(0, 4) {
+            0 => clo(ast),
+            1 => plus(ast),
+            2 => opt(ast),
+            _ => {
+                let min = rng.gen_range(0, 4);
+                let max = match rng.gen_range(0, 3) {
+                    0 => None,
+                    1 => Some(min),
+                    _ => Some(min + rng.gen_range(1, 3)),
+                };
+                rep(ast, min, max)
+            }
+        }
+    }
+
+    fn random_class(rng: &mut StdRng) -> AST {
+        if rng.gen_bool(0.3) {
+            return AST::Class(unicode::perl_class(*['d', 'D', 's', 'S', 'w', 'W'].choose(rng).unwrap()).unwrap());
+        }
+        let mut ranges = vec![];
+        for _ in 0..rng.gen_range(1, 4) {
+            let a = *CHARS.choose(rng).unwrap();
+            let b = *CHARS.choose(rng).unwrap();
+            ranges.push((a.min(b), a.max(b)));
+        }
+        cls(&ranges, rng.gen_bool(0.5))
+    }
+
+    #[test]
+    fn random_round_trips() {
+        for seed in 0..2000 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let ast = random_ast(&mut rng, &mut 0, Flags::default(), 5);
+            let pattern = ast.to_pattern();
+            assert_eq!(Parser::parse(Tokenizer::new(&pattern)), Ok(ast), "seed {} printed {:?}", seed, pattern);
+        }
+    }
+}
+
+/**
+ * Why a pattern couldn't be parsed: where in the pattern it went wrong,
+ * what the parser would have accepted there, and maybe how to fix it.
+ */
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    pub expected: Vec<String>,
+    pub hint: Option<String>,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, span: Span) -> ParseError {
+        ParseError {
+            message: message.into(),
+            span,
+            expected: vec![],
+            hint: None,
+        }
+    }
+
+    fn expecting(mut self, expected: &[&str]) -> ParseError {
+        self.expected = expected.iter().map(|e| String::from(*e)).collect();
+        self
+    }
+
+    fn with_hint(mut self, hint: impl Into<String>) -> ParseError {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /**
+     * The message followed by the line of the pattern the error is on,
+     * with carets under the span, then what was expected and the hint.
+     */
+    pub fn render(&self, pattern: &str) -> String {
+        let start = self.span.start.min(pattern.len());
+        let end = self.span.end.clamp(start, pattern.len());
+        let line_start = pattern[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = pattern[start..].find('\n').map_or(pattern.len(), |i| start + i);
+        // tabs are kept so the carets line up with what the terminal shows
+        let pad: String = pattern[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = pattern[start..end.min(line_end)].chars().count().max(1);
+
+        let mut out = format!("{}\n  {}\n  {}{}", self.message, &pattern[line_start..line_end], pad, "^".repeat(width));
+        if !self.expected.is_empty() {
+            out.push_str(&format!("\n  expected {}", one_of(&self.expected)));
+        }
+        if let Some(hint) = &self.hint {
+            out.push_str(&format!("\n  hint: {}", hint));
+        }
+        out
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// "a", "a or b", "a, b or c"
+fn one_of(items: &[String]) -> String {
+    match items.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+// what can start an atom, and what can go inside of brackets
+const ATOM_START: &[&str] = &["a char", "(", ".", "[", "\\", "^", "$"];
+const CLASS_MEMBER: &[&str] = &["a char", "a range like a-z", "\\", "[:name:]", "]"];
+
 pub struct Parser<'tokens> {
-    tokens: Peekable<Tokenizer<'tokens>>,
+    tokens: Peekable<Spanned<'tokens>>,
+    // span of the last token taken, where errors point
+    span: Span,
+    // every error found so far, the parse goes on past them
+    errors: Vec<ParseError>,
+    groups: usize,
+    names: Vec<String>,
+    flags: Flags,
 }
 
 impl<'tokens> Parser<'tokens> {
-    pub fn parse(tokenizer: Tokenizer<'tokens>) -> Result<AST, String> {
+    pub fn parse(tokenizer: Tokenizer<'tokens>) -> Result<AST, Error> {
+        Parser::parse_with_flags(tokenizer, Flags::default())
+    }
+
+    /**
+     * Parse a pattern that starts out with the given flags, as if it were
+     * wrapped in (?flags:...), e.g. for the -i option.
+     */
+    pub fn parse_with_flags(tokenizer: Tokenizer<'tokens>, flags: Flags) -> Result<AST, Error> {
         let mut parser = Parser {
-            tokens: tokenizer.peekable(),
+            tokens: tokenizer.spanned().peekable(),
+            span: Span::default(),
+            errors: vec![],
+            groups: 0,
+            names: vec![],
+            flags,
         };
 
         // start of recursive descent parsing, also checks if any tokens not parse at end
-        let out = parser.reg_expr()?;
-        if let Some(token) = parser.tokens.peek() {
-            Err(format!("Expected end of input, found {:?}", token))
+        let out = parser.reg_expr();
+        // only a ) can stop the parse early, and there may be more errors after it
+        while let Some((token, span)) = parser.tokens.next() {
+            let error = ParseError::new(format!("Expected end of input, found {:?}", token), span);
+            parser.errors.push(error.with_hint("this ) has no ( before it to close, write \\) to match a literal )"));
+            parser.reg_expr();
+        }
+        if parser.errors.is_empty() {
+            Ok(with_flags(Flags::default(), flags, out))
         } else {
-            Ok(out)
+            Err(Error::Syntax(parser.errors))
         }
     }
 }
@@ -64,6 +599,17 @@ impl<'tokens> Parser<'tokens> {
 mod parser_parse {
     use super::*;
 
+    fn syntax_errors(tokenizer: Tokenizer) -> Vec<ParseError> {
+        match Parser::parse(tokenizer) {
+            Err(Error::Syntax(errors)) => errors,
+            other => panic!("Expected syntax errors, got {:?}", other),
+        }
+    }
+
+    fn syntax_error(tokenizer: Tokenizer) -> ParseError {
+        syntax_errors(tokenizer).remove(0)
+    }
+
     #[test]
     fn simple_cat() {
         let par = Parser::parse(Tokenizer::new("aa")).unwrap();
@@ -109,7 +655,275 @@ mod parser_parse {
     #[test] // this is almost absurdly long but i guess it was good practice to parse through lol
     fn everything() {
         let par = Parser::parse(Tokenizer::new("b(oo*|a).m")).unwrap();
-        assert_eq!(cat(cha('b'), cat(alt(cat(cha('o'), clo(cha('o'))), cha('a')), cat(AST::AnyChar, cha('m')))), par);
+        assert_eq!(cat(cha('b'), cat(grp(1, alt(cat(cha('o'), clo(cha('o'))), cha('a'))), cat(AST::AnyChar, cha('m')))), par);
+    }
+
+    #[test]
+    fn escaped_literals() {
+        let par = Parser::parse(Tokenizer::new("1\\.2\\+")).unwrap();
+        assert_eq!(cat(cha('1'), cat(cha('.'), cat(cha('2'), cha('+')))), par);
+    }
+
+    #[test]
+    fn numbered_groups() {
+        let par = Parser::parse(Tokenizer::new("(a(b))(c)")).unwrap();
+        assert_eq!(cat(grp(1, cat(cha('a'), grp(2, cha('b')))), grp(3, cha('c'))), par);
+    }
+
+    #[test]
+    fn named_groups() {
+        let par = Parser::parse(Tokenizer::new("(?P<key>\\w)=(?:a|b)(c)")).unwrap();
+        let word = AST::Class(unicode::perl_class('w').unwrap());
+        assert_eq!(cat(named(1, "key", word), cat(cha('='), cat(alt(cha('a'), cha('b')), grp(2, cha('c'))))), par);
+    }
+
+    #[test]
+    fn duplicate_group_name() {
+        let par = Parser::parse(Tokenizer::new("(?P<x>a)|(?P<x>b)"));
+        assert_eq!(par.map_err(|e| e.to_string()), Err(String::from("Duplicate group name: x")));
+    }
+
+    #[test]
+    fn backrefs() {
+        let par = Parser::parse(Tokenizer::new("(a)\\1")).unwrap();
+        assert_eq!(cat(grp(1, cha('a')), AST::Backref(1)), par);
+    }
+
+    #[test]
+    fn bad_backref() {
+        let par = Parser::parse(Tokenizer::new("\\1(a)"));
+        assert_eq!(par.map_err(|e| e.to_string()), Err(String::from("Backreference \\1 refers to a group that does not exist")));
+    }
+
+    #[test]
+    fn flag_groups() {
+        let i = Flags::default().apply("i");
+        let par = Parser::parse(Tokenizer::new("a(?i:b)c")).unwrap();
+        assert_eq!(cat(cha('a'), cat(flg(i, cha('b')), cha('c'))), par);
+        let par = Parser::parse(Tokenizer::new("a(?i)bc")).unwrap();
+        assert_eq!(cat(cha('a'), flg(i, cat(cha('b'), cha('c')))), par);
+        let par = Parser::parse(Tokenizer::new("(?i)a")).unwrap();
+        assert_eq!(flg(i, cha('a')), par);
+    }
+
+    #[test]
+    fn flags_end_with_group() {
+        let i = Flags::default().apply("i");
+        let par = Parser::parse(Tokenizer::new("(a(?i)b|c)d")).unwrap();
+        assert_eq!(cat(grp(1, alt(cat(cha('a'), flg(i, cha('b'))), flg(i, cha('c')))), cha('d')), par);
+    }
+
+    #[test]
+    fn initial_flags() {
+        let i = Flags::default().apply("i");
+        let par = Parser::parse_with_flags(Tokenizer::new("a(?-i)b"), i).unwrap();
+        assert_eq!(flg(i, cat(cha('a'), flg(Flags::default(), cha('b')))), par);
+    }
+
+    #[test]
+    fn flags_turned_off() {
+        let i = Flags::default().apply("i");
+        let par = Parser::parse(Tokenizer::new("(?i)a(?-i:b)")).unwrap();
+        assert_eq!(flg(i, cat(cha('a'), flg(Flags::default(), cha('b')))), par);
+        let sm = Flags::default().apply("sm");
+        assert_eq!(sm.apply("-s"), Flags::default().apply("m"));
+    }
+
+    #[test]
+    fn lookarounds() {
+        let par = Parser::parse(Tokenizer::new("(?<!x)a(?=(b))")).unwrap();
+        let behind = AST::Lookaround(Lookaround::NotBehind, Box::new(cha('x')));
+        let ahead = AST::Lookaround(Lookaround::Ahead, Box::new(grp(1, cha('b'))));
+        assert_eq!(cat(behind, cat(cha('a'), ahead)), par);
+    }
+
+    #[test]
+    fn anchors() {
+        let par = Parser::parse(Tokenizer::new("^a|b$")).unwrap();
+        let start = AST::Assertion(Assertion::LineStart);
+        let end = AST::Assertion(Assertion::LineEnd);
+        assert_eq!(alt(cat(start, cha('a')), cat(cha('b'), end)), par);
+    }
+
+    #[test]
+    fn word_boundaries() {
+        let par = Parser::parse(Tokenizer::new("\\bi\\B")).unwrap();
+        let boundary = AST::Assertion(Assertion::WordBoundary);
+        let not_boundary = AST::Assertion(Assertion::NotWordBoundary);
+        assert_eq!(cat(boundary, cat(cha('i'), not_boundary)), par);
+    }
+
+    #[test]
+    fn repeats() {
+        let par = Parser::parse(Tokenizer::new("a{3}b{2,}c{1,4}")).unwrap();
+        assert_eq!(cat(rep(cha('a'), 3, Some(3)), cat(rep(cha('b'), 2, None), rep(cha('c'), 1, Some(4)))), par);
+    }
+
+    #[test]
+    fn optional() {
+        let par = Parser::parse(Tokenizer::new("colou?r")).unwrap();
+        assert_eq!(cat(cha('c'), cat(cha('o'), cat(cha('l'), cat(cha('o'), cat(opt(cha('u')), cha('r')))))), par);
+    }
+
+    #[test]
+    fn lazy_quantifiers() {
+        let par = Parser::parse(Tokenizer::new("a*?b+?c??d{2,}?")).unwrap();
+        assert_eq!(
+            cat(lazy(clo(cha('a'))), cat(lazy(plus(cha('b'))), cat(lazy(opt(cha('c'))), lazy(rep(cha('d'), 2, None))))),
+            par
+        );
+    }
+
+    #[test]
+    fn lazy_then_optional() {
+        let par = Parser::parse(Tokenizer::new("a???")).unwrap();
+        assert_eq!(opt(lazy(opt(cha('a')))), par);
+    }
+
+    #[test]
+    fn stacked_repeats() {
+        let par = Parser::parse(Tokenizer::new("(ab){2}{3}*")).unwrap();
+        assert_eq!(clo(rep(rep(grp(1, cat(cha('a'), cha('b'))), 2, Some(2)), 3, Some(3))), par);
+    }
+
+    #[test]
+    fn bad_repeat() {
+        let par = Parser::parse(Tokenizer::new("a{4,2}"));
+        assert_eq!(par.map_err(|e| e.to_string()), Err(String::from("Invalid repetition {4,2}: min is greater than max")));
+    }
+
+    #[test]
+    fn bracket_class() {
+        let par = Parser::parse(Tokenizer::new("[a-z0-9_]+")).unwrap();
+        assert_eq!(plus(cls(&[('a', 'z'), ('0', '9'), ('_', '_')], false)), par);
+    }
+
+    #[test]
+    fn negated_class() {
+        let par = Parser::parse(Tokenizer::new("x[^abc]")).unwrap();
+        assert_eq!(cat(cha('x'), cls(&[('a', 'c')], true)), par);
+    }
+
+    #[test]
+    fn perl_classes() {
+        let par = Parser::parse(Tokenizer::new("\\d[\\D_]")).unwrap();
+        let not_digit = unicode::perl_class('D').unwrap();
+        let mut class = CharClass::new();
+        class.push_class(&not_digit);
+        class.push_char('_');
+        assert_eq!(cat(AST::Class(unicode::perl_class('d').unwrap()), AST::Class(class)), par);
+    }
+
+    #[test]
+    fn properties() {
+        let par = Parser::parse(Tokenizer::new("\\p{Greek}\\PL")).unwrap();
+        let mut not_letter = unicode::property("L").unwrap();
+        not_letter.negate();
+        assert_eq!(cat(AST::Class(unicode::script("Greek").unwrap()), AST::Class(not_letter)), par);
+        let par = Parser::parse(Tokenizer::new("[\\p{Nd}_]")).unwrap();
+        let mut class = unicode::general_category("Nd").unwrap();
+        class.push_char('_');
+        assert_eq!(AST::Class(class), par);
+    }
+
+    #[test]
+    fn unknown_property() {
+        let par = Parser::parse(Tokenizer::new("\\p{Klingon}"));
+        assert_eq!(par.map_err(|e| e.to_string()), Err(String::from("Unknown Unicode property: \\p{Klingon}")));
+    }
+
+    #[test]
+    fn posix_classes() {
+        let par = Parser::parse(Tokenizer::new("[[:upper:][:digit:]_]")).unwrap();
+        assert_eq!(cls(&[('A', 'Z'), ('0', '9'), ('_', '_')], false), par);
+        let par = Parser::parse(Tokenizer::new("[^[:space:]]")).unwrap();
+        assert_eq!(cls(&[('\t', '\r'), (' ', ' ')], true), par);
+    }
+
+    #[test]
+    fn unknown_posix_class() {
+        let par = Parser::parse(Tokenizer::new("[[:alfa:]]"));
+        assert_eq!(par.map_err(|e| e.to_string()), Err(String::from("Unknown POSIX character class: [:alfa:]")));
+    }
+
+    #[test]
+    fn bad_range() {
+        let par = Parser::parse(Tokenizer::new("[z-a]"));
+        assert_eq!(par.map_err(|e| e.to_string()), Err(String::from("Invalid range in bracket expression: z-a")));
+    }
+
+    #[test]
+    fn unclosed_class() {
+        let par = Parser::parse(Tokenizer::new("[]"));
+        assert_eq!(par.map_err(|e| e.to_string()), Err(String::from("Unexpected end of input")));
+    }
+
+    #[test]
+    fn error_spans() {
+        let error = syntax_error(Tokenizer::new("ab|*c"));
+        assert_eq!(error.span, Span::new(3, 4));
+        assert!(error.expected.contains(&String::from("(")));
+        assert!(error.hint.is_some());
+        let error = syntax_error(Tokenizer::new("x[z-a]"));
+        assert_eq!(error.span, Span::new(2, 5));
+        let error = syntax_error(Tokenizer::new("(ab"));
+        assert_eq!(error.span, Span::new(3, 3));
+        assert_eq!(error.expected, vec![String::from(")")]);
+        let error = syntax_error(Tokenizer::new("ab(c*"));
+        assert_eq!(error.span, Span::new(5, 5));
+        let error = syntax_error(Tokenizer::new("a)"));
+        assert_eq!(error.span, Span::new(1, 2));
+    }
+
+    #[test]
+    fn all_errors() {
+        let errors = syntax_errors(Tokenizer::new("a{3,1}|(b|*)|[z-a]|c)|\\9"));
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Invalid repetition {3,1}: min is greater than max",
+                "Unexpected token: KleeneStar",
+                "Invalid range in bracket expression: z-a",
+                "Expected end of input, found RParen",
+                "Backreference \\9 refers to a group that does not exist",
+            ]
+        );
+        assert_eq!(errors[1].span, Span::new(10, 11));
+        assert_eq!(errors[4].span, Span::new(22, 24));
+    }
+
+    #[test]
+    fn recovery_skips_groups() {
+        let errors = syntax_errors(Tokenizer::new("a[b-a](c(d)e)|f(|+)"));
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec!["Invalid range in bracket expression: b-a", "Unexpected token: KleenePlus"]);
+        let errors = syntax_errors(Tokenizer::new("((a|*)"));
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec!["Unexpected token: KleeneStar", "Unexpected end of input"]);
+        let errors = syntax_errors(Tokenizer::new("(?P<x>a)(?P<x>b)|*"));
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec!["Duplicate group name: x", "Unexpected token: KleeneStar"]);
+    }
+
+    #[test]
+    fn render_error() {
+        let pattern = "é|\\p{Klingon}";
+        let error = syntax_error(Tokenizer::new(pattern));
+        assert_eq!(
+            error.render(pattern),
+            "Unknown Unicode property: \\p{Klingon}\n  é|\\p{Klingon}\n    ^^^^^^^^^^^\n  hint: try a general \
+             category like L or Nd, a script like Greek, or Alphabetic, White_Space or Join_Control"
+        );
+        let pattern = "a|\n\t*b";
+        let error = syntax_error(Tokenizer::with_verbose(pattern, true));
+        assert!(error.render(pattern).starts_with("Unexpected token: KleeneStar\n  \t*b\n  \t^\n  expected a char, (, .,"));
+    }
+
+    #[test]
+    fn bad_escape() {
+        let par = Parser::parse(Tokenizer::new("a\\q"));
+        assert_eq!(par.map_err(|e| e.to_string()), Err(String::from("Unknown escape sequence: \\q")));
     }
 }
 
@@ -117,32 +931,83 @@ mod parser_parse {
 impl <'tokens> Parser<'tokens> {
     
     // RegExpr ::= Catenation (UnionBar RegExpr)?
-    fn reg_expr(&mut self) -> Result<AST, String> {
-        let cat_result = self.catenation()?;
-        if let Some(t) = self.tokens.peek() {
+    // a branch with an error is recorded and skipped up to the | or ) that
+    // ends it, so the errors in the rest of the pattern are found too
+    fn reg_expr(&mut self) -> AST {
+        let outer = self.flags;
+        let cat_result = match self.catenation() {
+            Ok(ast) => ast,
+            Err(error) => {
+                self.errors.push(error);
+                self.synchronize();
+                AST::Empty
+            },
+        };
+        if let Some(t) = self.peek() {
             match t {
                 Token::UnionBar => {
-                    self.consume_token(Token::UnionBar);
-                    Ok(alt(cat_result, self.reg_expr()?))
+                    self.tokens.next();
+                    // flags set in this branch carry on into the branches after it
+                    let inner = self.flags;
+                    let rhs = self.reg_expr();
+                    alt(cat_result, with_flags(outer, inner, rhs))
                 },
-                _ => Ok(cat_result),
+                _ => cat_result,
             }
         } else {
-            Ok(cat_result)
+            cat_result
         }
     }
 
+    // skip to the next | or ) at this depth, along with any groups opened on the way
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::UnionBar | Token::RParen if depth == 0 => break,
+                Token::RParen => depth -= 1,
+                Token::LParen | Token::NonCapturing | Token::NamedGroup(_) | Token::FlagGroup(_) => depth += 1,
+                Token::LookAhead | Token::NotLookAhead | Token::LookBehind | Token::NotLookBehind => depth += 1,
+                _ => {},
+            }
+            self.tokens.next();
+        }
+    }
 
-    // Catenation ::= Closure (Catenation)?
-    fn catenation(&mut self) -> Result<AST, String> {
+
+    // Catenation ::= [Flags]? Closure (Catenation)? | Empty
+    fn catenation(&mut self) -> Result<AST, ParseError> {
+        if let Some(Token::Flags(_)) = self.peek() {
+            if let Some(rest) = self.flags()? {
+                return Ok(rest);
+            }
+        }
+        // nothing before a |, a ) or the end matches the empty string
+        if let None | Some(Token::UnionBar) | Some(Token::RParen) = self.peek() {
+            return Ok(AST::Empty);
+        }
         let clo_result = self.closure()?;
-        if let Some(t) = self.tokens.peek() {
+        if let Some(t) = self.peek() {
             match t {
+                Token::Flags(_) => match self.flags()? {
+                    Some(rest) => Ok(cat(clo_result, rest)),
+                    None => Ok(clo_result),
+                },
                 Token::LParen => {
-                    Ok(cat(clo_result, self.catenation().unwrap()))
+                    Ok(cat(clo_result, self.catenation()?))
                 },
-                Token::AnyChar => Ok(cat(clo_result, self.catenation().unwrap())),
-                Token::Char(c) => Ok(cat(clo_result, self.catenation().unwrap())),
+                Token::NonCapturing | Token::NamedGroup(_) | Token::FlagGroup(_) => Ok(cat(clo_result, self.catenation()?)),
+                Token::Backref(_) => Ok(cat(clo_result, self.catenation()?)),
+                Token::LookAhead | Token::NotLookAhead => Ok(cat(clo_result, self.catenation()?)),
+                Token::LookBehind | Token::NotLookBehind => Ok(cat(clo_result, self.catenation()?)),
+                Token::AnyChar => Ok(cat(clo_result, self.catenation()?)),
+                Token::Char(_) => Ok(cat(clo_result, self.catenation()?)),
+                Token::LBracket => Ok(cat(clo_result, self.catenation()?)),
+                Token::PerlClass(_) => Ok(cat(clo_result, self.catenation()?)),
+                Token::Property(_) | Token::NotProperty(_) => Ok(cat(clo_result, self.catenation()?)),
+                Token::Caret | Token::Dollar => Ok(cat(clo_result, self.catenation()?)),
+                Token::WordBoundary | Token::NotWordBoundary => Ok(cat(clo_result, self.catenation()?)),
+                Token::Error(_) => Ok(cat(clo_result, self.catenation()?)),
                 _ => Ok(clo_result),
             }
         } else {
@@ -150,40 +1015,267 @@ impl <'tokens> Parser<'tokens> {
         }
     }
 
-    // Closure ::= Atom [KleeneStar|KleenePlus]?
-    fn closure(&mut self) -> Result<AST, String> {
-        let atom_result = self.atom()?;
-        if let Some(t) = self.tokens.peek() {
-            match t {
-                Token::KleeneStar => {
-                    self.consume_token(Token::KleeneStar);
-                    Ok(clo(atom_result))
+    // Closure ::= Atom ([KleeneStar|KleenePlus|Question|Repeat] [Question]?)*
+    fn closure(&mut self) -> Result<AST, ParseError> {
+        let mut atom_result = self.atom()?;
+        loop {
+            let quantified = match self.peek() {
+                Some(Token::KleeneStar) => {
+                    self.consume_token(Token::KleeneStar)?;
+                    clo(atom_result)
                 },
-                Token::KleenePlus => {
-                    self.consume_token(Token::KleenePlus);
-                    Ok(plus(atom_result))
+                Some(Token::KleenePlus) => {
+                    self.consume_token(Token::KleenePlus)?;
+                    plus(atom_result)
                 },
-                _ => Ok(atom_result),
-            }
-        } else {
-            Ok(atom_result)
+                Some(Token::Question) => {
+                    self.consume_token(Token::Question)?;
+                    opt(atom_result)
+                },
+                Some(&Token::Repeat(min, max)) => {
+                    self.consume_token(Token::Repeat(min, max))?;
+                    match max {
+                        Some(max) if max < min => {
+                            let message = format!("Invalid repetition {{{},{}}}: min is greater than max", min, max);
+                            return Err(ParseError::new(message, self.span).with_hint(format!("write it as {{{},{}}}", max, min)))
+                        },
+                        _ => rep(atom_result, min, max),
+                    }
+                },
+                _ => return Ok(atom_result),
+            };
+
+            // a ? right after a quantifier makes it lazy
+            atom_result = match self.peek() {
+                Some(Token::Question) => {
+                    self.consume_token(Token::Question)?;
+                    lazy(quantified)
+                },
+                _ => quantified,
+            };
         }
     }
 
-    // Atom ::= [LParen] RegExpr [RParen]|[AnyChar]|[Char]
-    fn atom(&mut self) -> Result<AST, String> {
-        let t = self.take_next_token()?;
+    // Atom ::= [LParen|NamedGroup|NonCapturing|FlagGroup|Look*] RegExpr [RParen]|[AnyChar]|[Char]|Class|Assertion|[Backref]
+    fn atom(&mut self) -> Result<AST, ParseError> {
+        let t = self.take_next_token().map_err(|e| e.expecting(ATOM_START))?;
         match t {
-            Token::LParen => {
-                let expr =  self.reg_expr();
-                self.consume_token(Token::RParen)?;
-                expr
+            Token::LParen => self.group(None),
+            Token::NamedGroup(name) => {
+                // the group itself is fine, so keep parsing it after noting the name
+                if self.names.contains(&name) {
+                    let error = ParseError::new(format!("Duplicate group name: {}", name), self.span);
+                    self.errors.push(error.with_hint("each name can only be given to one group"));
+                }
+                self.names.push(name.clone());
+                self.group(Some(name))
+            },
+            Token::NonCapturing => {
+                let outer = self.flags;
+                let expr = self.reg_expr();
+                self.close_group()?;
+                self.flags = outer;
+                Ok(expr)
+            },
+            Token::LookAhead => self.lookaround(Lookaround::Ahead),
+            Token::NotLookAhead => self.lookaround(Lookaround::NotAhead),
+            Token::LookBehind => self.lookaround(Lookaround::Behind),
+            Token::NotLookBehind => self.lookaround(Lookaround::NotBehind),
+            Token::FlagGroup(spec) => {
+                let outer = self.flags;
+                self.flags = outer.apply(&spec);
+                let inner = self.flags;
+                let expr = self.reg_expr();
+                self.close_group()?;
+                self.flags = outer;
+                Ok(with_flags(outer, inner, expr))
             },
             Token::AnyChar => Ok(AST::AnyChar),
             Token::Char(c) => Ok(cha(c)),
-            _ => Err(format!("Unexpected token: {:?}", t)),
+            Token::LBracket => self.class(),
+            Token::PerlClass(c) => Ok(AST::Class(self.perl_class(c)?)),
+            Token::Property(name) => Ok(AST::Class(self.property(&name, false)?)),
+            Token::NotProperty(name) => Ok(AST::Class(self.property(&name, true)?)),
+            Token::Caret => Ok(AST::Assertion(Assertion::LineStart)),
+            Token::Dollar => Ok(AST::Assertion(Assertion::LineEnd)),
+            Token::WordBoundary => Ok(AST::Assertion(Assertion::WordBoundary)),
+            Token::NotWordBoundary => Ok(AST::Assertion(Assertion::NotWordBoundary)),
+            // only groups that have already been opened can be referred back to
+            Token::Backref(index) if index <= self.groups => Ok(AST::Backref(index)),
+            Token::Backref(index) => {
+                let message = format!("Backreference \\{} refers to a group that does not exist", index);
+                let hint = match self.groups {
+                    0 => String::from("no group is opened before it"),
+                    1 => String::from("only \\1 is opened before it"),
+                    n => format!("only \\1 to \\{} are opened before it", n),
+                };
+                Err(ParseError::new(message, self.span).with_hint(hint))
+            },
+            Token::Error(msg) => Err(ParseError::new(msg, self.span)),
+            Token::KleeneStar | Token::KleenePlus | Token::Question | Token::Repeat(_, _) => {
+                Err(ParseError::new(format!("Unexpected token: {:?}", t), self.span)
+                    .expecting(ATOM_START)
+                    .with_hint("there is nothing before it to repeat, escape it with \\ to match it literally"))
+            },
+            _ => Err(ParseError::new(format!("Unexpected token: {:?}", t), self.span).expecting(ATOM_START)),
+        }
+    }
+
+    // (?flags) changes the flags up to the end of the group it is in, and
+    // the rest of this catenation gets wrapped in them if there is a rest
+    fn flags(&mut self) -> Result<Option<AST>, ParseError> {
+        let outer = self.flags;
+        if let Token::Flags(spec) = self.take_next_token()? {
+            self.flags = outer.apply(&spec);
+        }
+        let inner = self.flags;
+        match self.peek() {
+            None | Some(Token::UnionBar) | Some(Token::RParen) => Ok(None),
+            Some(_) => {
+                let rest = self.catenation()?;
+                Ok(Some(with_flags(outer, inner, rest)))
+            },
         }
     }
+
+    fn lookaround(&mut self, kind: Lookaround) -> Result<AST, ParseError> {
+        let outer = self.flags;
+        let expr = self.reg_expr();
+        self.close_group()?;
+        self.flags = outer;
+        Ok(AST::Lookaround(kind, Box::new(expr)))
+    }
+
+    fn group(&mut self, name: Option<String>) -> Result<AST, ParseError> {
+        // groups are numbered by the order of their opening paren, named or not
+        self.groups += 1;
+        let index = self.groups;
+        let outer = self.flags;
+        let expr = self.reg_expr();
+        self.close_group()?;
+        self.flags = outer;
+        Ok(AST::Group {
+            index,
+            name,
+            ast: Box::new(expr),
+        })
+    }
+}
+
+// this is the bracket expression part of the grammar
+impl<'tokens> Parser<'tokens> {
+    // Class ::= [LBracket] [Caret]? (Member)+ [RBracket]
+    fn class(&mut self) -> Result<AST, ParseError> {
+        let mut class = CharClass::new();
+        if let Some(Token::Caret) = self.peek() {
+            self.consume_token(Token::Caret)?;
+            class.negate();
+        }
+        loop {
+            let token = self.take_next_token().map_err(|e| {
+                e.expecting(CLASS_MEMBER)
+                    .with_hint("the [ that opens this bracket expression is never closed")
+            })?;
+            match token {
+                Token::RBracket => break,
+                Token::Char(c) => self.class_member(&mut class, c)?,
+                Token::PerlClass(c) => class.push_class(&self.perl_class(c)?),
+                Token::Property(name) => class.push_class(&self.property(&name, false)?),
+                Token::NotProperty(name) => class.push_class(&self.property(&name, true)?),
+                Token::PosixClass(name) => {
+                    let ranges = posix_class(&name).map_err(|message| {
+                        let names: Vec<&str> = POSIX_CLASSES.iter().map(|(name, _)| *name).collect();
+                        ParseError::new(message, self.span).with_hint(format!("the classes are {}", names.join(", ")))
+                    })?;
+                    class.push_ranges(ranges)
+                },
+                Token::Error(msg) => return Err(ParseError::new(msg, self.span)),
+                t => {
+                    let message = format!("Unexpected token in bracket expression: {:?}", t);
+                    return Err(ParseError::new(message, self.span).expecting(CLASS_MEMBER));
+                },
+            }
+        }
+        Ok(AST::Class(class))
+    }
+
+    // Member ::= [Char] ([Dash] [Char])?
+    fn class_member(&mut self, class: &mut CharClass, lo: char) -> Result<(), ParseError> {
+        if let Some(Token::Dash) = self.peek() {
+            let from = self.span;
+            self.consume_token(Token::Dash)?;
+            match self.take_next_token().map_err(|e| e.expecting(&["a char"]))? {
+                Token::Char(hi) if lo <= hi => class.push_range(lo, hi),
+                Token::Char(hi) => {
+                    let message = format!("Invalid range in bracket expression: {}-{}", lo, hi);
+                    let error = ParseError::new(message, from.to(self.span));
+                    return Err(error.with_hint(format!("write it as {}-{}", hi, lo)));
+                },
+                Token::Error(msg) => return Err(ParseError::new(msg, self.span)),
+                t => {
+                    let message = format!("Unexpected token in bracket expression: {:?}", t);
+                    return Err(ParseError::new(message, self.span).expecting(&["a char"]));
+                },
+            }
+        } else {
+            class.push_char(lo);
+        }
+        Ok(())
+    }
+
+    fn perl_class(&self, c: char) -> Result<CharClass, ParseError> {
+        unicode::perl_class(c).ok_or_else(|| ParseError::new(format!("Unknown class escape: \\{}", c), self.span))
+    }
+
+    fn property(&self, name: &str, negated: bool) -> Result<CharClass, ParseError> {
+        let mut class = unicode::property(name).ok_or_else(|| {
+            ParseError::new(format!("Unknown Unicode property: \\p{{{}}}", name), self.span)
+                .with_hint("try a general category like L or Nd, a script like Greek, or Alphabetic, White_Space or Join_Control")
+        })?;
+        if negated {
+            class.negate();
+        }
+        Ok(class)
+    }
+}
+
+// only wrap in a Flags node when they actually changed
+fn with_flags(outer: Flags, inner: Flags, ast: AST) -> AST {
+    if outer == inner {
+        ast
+    } else {
+        flg(inner, ast)
+    }
+}
+
+/**
+ * The POSIX named classes allowed inside brackets, e.g. [[:alpha:]_].
+ * Like most modern engines these only cover ASCII; \w, \d and \s are
+ * the unicode aware spellings.
+ */
+const POSIX_CLASSES: &[(&str, &[(char, char)])] = &[
+    ("alnum", &[('0', '9'), ('A', 'Z'), ('a', 'z')]),
+    ("alpha", &[('A', 'Z'), ('a', 'z')]),
+    ("ascii", &[('\0', '\x7F')]),
+    ("blank", &[('\t', '\t'), (' ', ' ')]),
+    ("cntrl", &[('\0', '\x1F'), ('\x7F', '\x7F')]),
+    ("digit", &[('0', '9')]),
+    ("graph", &[('!', '~')]),
+    ("lower", &[('a', 'z')]),
+    ("print", &[(' ', '~')]),
+    ("punct", &[('!', '/'), (':', '@'), ('[', '`'), ('{', '~')]),
+    ("space", &[('\t', '\r'), (' ', ' ')]),
+    ("upper", &[('A', 'Z')]),
+    ("word", &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')]),
+    ("xdigit", &[('0', '9'), ('A', 'F'), ('a', 'f')]),
+];
+
+fn posix_class(name: &str) -> Result<&'static [(char, char)], String> {
+    POSIX_CLASSES
+        .iter()
+        .find(|(class, _)| *class == name)
+        .map(|(_, ranges)| *ranges)
+        .ok_or(format!("Unknown POSIX character class: [:{}:]", name))
 }
 
 /** write tests for private api */
@@ -194,28 +1286,31 @@ mod parser_recur {
     #[test]
     fn clear_paren() {
         assert_eq!(Parser::from("a").atom().unwrap(), cha('a'));
-        assert_eq!(Parser::from("(a)").atom().unwrap(), cha('a'));
-        assert_eq!(Parser::from("((a))").atom().unwrap(), cha('a'));
+        assert_eq!(Parser::from("(a)").atom().unwrap(), grp(1, cha('a')));
+        assert_eq!(Parser::from("((a))").atom().unwrap(), grp(1, grp(2, cha('a'))));
     }
 
     #[test]
     fn empty_paren() {
-        assert_eq!(Parser::from("()").atom(), Err(String::from("Unexpected end of input")));
+        assert_eq!(Parser::from("()").atom(), Ok(grp(1, AST::Empty)));
+        assert_eq!(Parser::from("(|b)").atom(), Ok(grp(1, alt(AST::Empty, cha('b')))));
     }
 
     #[test]
     fn kleene_no_char() {
-        assert_eq!(Parser::from("*").atom(), Err(String::from("Unexpected token: KleeneStar")));
+        assert_eq!(Parser::from("*").atom().map_err(|e| e.to_string()), Err(String::from("Unexpected token: KleeneStar")));
     }
 
     #[test]
     fn no_union() {
-        assert_eq!(Parser::from("a|").reg_expr(), Err(String::from("Unexpected end of input")));
+        assert_eq!(Parser::from("a|").reg_expr(), alt(cha('a'), AST::Empty));
+        assert_eq!(Parser::from("").reg_expr(), AST::Empty);
+        assert_eq!(Parser::from("(?i)").reg_expr(), AST::Empty);
     }
 
     #[test]
     fn unclosed_paren() {
-        assert_eq!(Parser::from("(a").atom(), Err(String::from("Unexpected end of input")));
+        assert_eq!(Parser::from("(a").atom().map_err(|e| e.to_string()), Err(String::from("Unexpected end of input")));
     }
 }
 
@@ -225,29 +1320,43 @@ impl<'tokens> Parser<'tokens> {
     // we'll use this method for testing of the parser
     fn from(input: &'tokens str) -> Parser<'tokens> {
         Parser {
-            tokens: Tokenizer::new(input).peekable(),
+            tokens: Tokenizer::new(input).spanned().peekable(),
+            span: Span::default(),
+            errors: vec![],
+            groups: 0,
+            names: vec![],
+            flags: Flags::default(),
         }
     }
 
-    fn take_next_token(&mut self) -> Result<Token, String> {
-        if let Some(token) = self.tokens.next() {
+    fn peek(&mut self) -> Option<&Token> {
+        self.tokens.peek().map(|(token, _)| token)
+    }
+
+    fn take_next_token(&mut self) -> Result<Token, ParseError> {
+        if let Some((token, span)) = self.tokens.next() {
+            self.span = span;
             Ok(token)
         } else {
-            Err(String::from("Unexpected end of input"))
+            // points just past the last token
+            Err(ParseError::new("Unexpected end of input", Span::new(self.span.end, self.span.end)))
         }
     }
 
-    fn consume_token(&mut self, expected: Token) -> Result<Token, String> {
-        if let Some(next) = self.tokens.next() {
-            if next != expected {
-                Err(format!("Expected: {:?} - Found {:?}", expected, next))
-            } else {
-                Ok(next)
-            }
+    fn consume_token(&mut self, expected: Token) -> Result<Token, ParseError> {
+        let next = self.take_next_token()?;
+        if next != expected {
+            Err(ParseError::new(format!("Expected: {:?} - Found {:?}", expected, next), self.span))
         } else {
-            Err(String::from("Unexpected end of input"))
+            Ok(next)
         }
     }
 
+    // the ) at the end of any kind of group
+    fn close_group(&mut self) -> Result<Token, ParseError> {
+        self.consume_token(Token::RParen)
+            .map_err(|e| e.expecting(&[")"]).with_hint("the ( that opens this group is never closed"))
+    }
+
 
 }
@@ -0,0 +1,284 @@
+use super::charclass::CharClass;
+use super::parser::{alt, cat, clo, lazy, opt, plus, rep, AST};
+use super::visit::Fold;
+
+/*
+ * Tar Heel egrep - simplifier
+ *
+ * Rewrites an AST into a smaller one before it is turned into an NFA.
+ * Every rewrite keeps the groups where they are and keeps the order the
+ * Pike VM prefers choices in, so the simplified pattern finds the same
+ * leftmost-first matches with the same captures. That is why branches
+ * are only merged or factored with their neighbours, prefixes are only
+ * factored out when they can match just one way, and quantifiers are
+ * only collapsed when there is no capture group between them.
+ */
+
+/**
+ * An equivalent AST with nested catenations and alternations flattened,
+ * common prefixes of neighbouring branches factored out (ab|ac becomes
+ * a[bc]), nested quantifiers collapsed ((?:a+)* becomes a*), repeated
+ * a*a* collapsed to a*, and neighbouring single char branches merged
+ * into one class.
+ */
+pub fn simplify(ast: &AST) -> AST {
+    Simplify.fold(ast.clone())
+}
+
+struct Simplify;
+
+impl Fold for Simplify {
+    fn fold_alternation(&mut self, lhs: AST, rhs: AST) -> AST {
+        let mut unsimplified = vec![];
+        flatten_branches(lhs, &mut unsimplified);
+        flatten_branches(rhs, &mut unsimplified);
+        let mut branches = vec![];
+        for branch in unsimplified {
+            flatten_branches(self.fold(branch), &mut branches);
+        }
+        alternation(branches)
+    }
+
+    fn fold_catenation(&mut self, lhs: AST, rhs: AST) -> AST {
+        let mut unsimplified = vec![];
+        flatten_factors(lhs, &mut unsimplified);
+        flatten_factors(rhs, &mut unsimplified);
+        let mut factors = vec![];
+        for factor in unsimplified {
+            flatten_factors(self.fold(factor), &mut factors);
+        }
+        catenation(factors)
+    }
+
+    fn fold_closure(&mut self, ast: AST) -> AST {
+        match self.fold(ast) {
+            AST::Closure(inner) | AST::OneOrMore(inner) | AST::Optional(inner) => clo(*inner),
+            inner => clo(inner),
+        }
+    }
+
+    fn fold_one_or_more(&mut self, ast: AST) -> AST {
+        match self.fold(ast) {
+            AST::Closure(inner) => clo(*inner),
+            AST::OneOrMore(inner) => plus(*inner),
+            inner => plus(inner),
+        }
+    }
+
+    fn fold_optional(&mut self, ast: AST) -> AST {
+        match self.fold(ast) {
+            AST::Closure(inner) => clo(*inner),
+            AST::Optional(inner) => opt(*inner),
+            inner => opt(inner),
+        }
+    }
+
+    // a lazy quantifier prefers fewer repetitions than the greedy one
+    // nested inside it would, so only what it repeats is simplified
+    fn fold_lazy(&mut self, ast: AST) -> AST {
+        lazy(match ast {
+            AST::Closure(inner) => clo(self.fold(*inner)),
+            AST::OneOrMore(inner) => plus(self.fold(*inner)),
+            AST::Optional(inner) => opt(self.fold(*inner)),
+            AST::Repeat { ast, min, max } => rep(self.fold(*ast), min, max),
+            ast => self.fold(ast),
+        })
+    }
+}
+
+fn flatten_branches(ast: AST, branches: &mut Vec<AST>) {
+    match ast {
+        AST::Alternation(lhs, rhs) => {
+            flatten_branches(*lhs, branches);
+            flatten_branches(*rhs, branches);
+        }
+        ast => branches.push(ast),
+    }
+}
+
+// empty factors match nothing and are dropped, and x*x* is just x*
+fn flatten_factors(ast: AST, factors: &mut Vec<AST>) {
+    match ast {
+        AST::Catenation(lhs, rhs) => {
+            flatten_factors(*lhs, factors);
+            flatten_factors(*rhs, factors);
+        }
+        AST::Empty => {}
+        AST::Closure(ref x) if matches_one_char(x) && factors.last() == Some(&ast) => {}
+        ast => factors.push(ast),
+    }
+}
+
+// rebuild the right nested chain the parser makes
+fn catenation(factors: Vec<AST>) -> AST {
+    factors.into_iter().rev().reduce(|rhs, lhs| cat(lhs, rhs)).unwrap_or(AST::Empty)
+}
+
+fn alternation(branches: Vec<AST>) -> AST {
+    // a later copy of a branch can only match where the earlier one already did
+    let mut unique: Vec<AST> = vec![];
+    for branch in branches {
+        if !unique.contains(&branch) {
+            unique.push(branch);
+        }
+    }
+
+    let mut factored: Vec<AST> = vec![];
+    let mut rest = unique.into_iter().map(|branch| {
+        let mut factors = vec![];
+        flatten_factors(branch, &mut factors);
+        factors
+    });
+    let mut next = rest.next();
+    while let Some(factors) = next {
+        // the neighbours that start with the same prefix as this branch
+        let mut run = vec![factors];
+        next = rest.next();
+        while let Some(factors) = next.take() {
+            match (run[0].first(), factors.first()) {
+                (Some(prefix), Some(first)) if matches_one_way(prefix) && prefix == first => {
+                    run.push(factors);
+                    next = rest.next();
+                }
+                _ => {
+                    next = Some(factors);
+                    break;
+                }
+            }
+        }
+        if run.len() == 1 {
+            factored.push(catenation(run.remove(0)));
+            continue;
+        }
+        let prefix = run[0][0].clone();
+        let mut suffixes = vec![];
+        for mut factors in run {
+            factors.remove(0);
+            flatten_branches(catenation(factors), &mut suffixes);
+        }
+        let mut factors = vec![prefix];
+        flatten_factors(alternation(suffixes), &mut factors);
+        factored.push(catenation(factors));
+    }
+
+    // neighbouring branches that are one char each become one class
+    let mut branches: Vec<AST> = vec![];
+    for branch in factored {
+        match (branches.last_mut(), char_set(&branch)) {
+            (Some(last), Some(class)) if char_set(last).is_some() => {
+                let mut merged = char_set(last).unwrap();
+                merged.push_class(&class);
+                *last = AST::Class(merged);
+            }
+            _ => branches.push(branch),
+        }
+    }
+    branches.into_iter().rev().reduce(|rhs, lhs| alt(lhs, rhs)).unwrap_or(AST::Empty)
+}
+
+// the chars a single char branch matches; negated classes are left alone
+// since with case ignored they match differently than their ranges would
+fn char_set(ast: &AST) -> Option<CharClass> {
+    match ast {
+        AST::Char(c) => Some(CharClass::from_ranges(&[(*c, *c)])),
+        AST::Class(class) if !class.is_negated() => Some(class.clone()),
+        _ => None,
+    }
+}
+
+fn matches_one_char(ast: &AST) -> bool {
+    matches!(ast, AST::Char(_) | AST::AnyChar | AST::Class(_))
+}
+
+// only these can be factored out of branches without changing which of
+// their matches is preferred
+fn matches_one_way(ast: &AST) -> bool {
+    matches_one_char(ast) || matches!(ast, AST::Assertion(_) | AST::Backref(_))
+}
+
+#[cfg(test)]
+mod simplify {
+    use super::*;
+    use crate::backtrack::Backtrack;
+    use crate::nfa::NFA;
+    use crate::parser::Parser;
+    use crate::tokenizer::Tokenizer;
+
+    fn simplified(pattern: &str) -> String {
+        simplify(&Parser::parse(Tokenizer::new(pattern)).unwrap()).to_pattern()
+    }
+
+    #[test]
+    fn flatten() {
+        assert_eq!(simplified("(?:ab)(?:c(?:d))"), "abcd");
+        assert_eq!(simplified("(?:a|b(?:))|(?:c|)d"), "[ab]|(?:c|)d");
+        assert_eq!(simplified("x(?:)y(?:)*"), "xy(?:)*");
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(simplified("ab|ac"), "a[bc]");
+        assert_eq!(simplified("cat|car|cart|dog"), "ca(?:t|r(?:|t))|dog");
+        assert_eq!(simplified("ab|a|ab"), "a(?:b|)");
+        assert_eq!(simplified("ab|xy|ac"), "ab|xy|ac");
+        assert_eq!(simplified("(?:a|ab)c|(?:a|ab)d"), "a(?:(?:|b)c|(?:|b)d)");
+        assert_eq!(simplified("a*b|a*c|(x)y|(x)z"), "a*b|a*c|(x)y|(x)z");
+    }
+
+    #[test]
+    fn quantifiers() {
+        assert_eq!(simplified("(?:a*)*(?:b+)*(?:c*)+(?:d?)?(?:e+)+"), "a*b*c*d?e+");
+        assert_eq!(simplified("a*a*b+b+"), "a*b+b+");
+        assert_eq!(simplified("(?:a*)??"), "(?:a*)??");
+        assert_eq!(simplified("(a*)*"), "(a*)*");
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(simplified("a|b|[c-e]|[^x]|y|z"), "[a-e]|[^x]|[yz]");
+        assert_eq!(simplified("a|bc|d"), "a|bc|d");
+        match simplify(&Parser::parse(Tokenizer::new("_|\\d")).unwrap()) {
+            AST::Class(class) => assert!(class.contains('_') && class.contains('7') && class.contains('٣')),
+            other => panic!("Expected a class, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn smaller_nfa() {
+        let mut words = vec![];
+        for a in "abcd".chars() {
+            for b in "abcd".chars() {
+                for c in "abcd".chars() {
+                    words.push(format!("{}{}{}", a, b, c));
+                }
+            }
+        }
+        let pattern = words.join("|");
+        let ast = Parser::parse(Tokenizer::new(&pattern)).unwrap();
+        assert_eq!(simplify(&ast).to_pattern(), "a(?:a[a-d]|b[a-d]|c[a-d]|d[a-d])|b(?:a[a-d]|b[a-d]|c[a-d]|d[a-d])|c(?:a[a-d]|b[a-d]|c[a-d]|d[a-d])|d(?:a[a-d]|b[a-d]|c[a-d]|d[a-d])");
+        assert!(crate::nfa::expanded_size(&simplify(&ast)) < crate::nfa::expanded_size(&ast) / 4);
+        let nfa = NFA::from(&pattern).unwrap();
+        assert!(words.iter().all(|word| nfa.accepts(word)));
+        assert!(!nfa.accepts("abe"));
+    }
+
+    #[test]
+    fn same_matches() {
+        let patterns = [
+            "cat|car|cart|c",
+            "(?:ab|a)(?:bc|b)*",
+            "(a|b)*(?:a|ab)c|x",
+            "(?i)x|y|Z|(?:z*)*",
+            "(?:a+)+?b|ab",
+            "\\bfoo|\\bbar|\\bfoe",
+        ];
+        let texts = ["cart", "carts c", "abbc", "bbabc", "xyzZ", "aab", "food barn foe"];
+        for pattern in patterns.iter() {
+            let nfa = NFA::from(pattern).unwrap();
+            let backtrack = Backtrack::from(pattern).unwrap();
+            for text in texts.iter() {
+                assert_eq!(nfa.captures(text), backtrack.captures(text).unwrap(), "{} on {}", pattern, text);
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-#![allow(unused)]
+#![allow(unused, clippy::empty_line_after_doc_comments)]
 
 /**
  *
@@ -11,27 +11,46 @@
  *
  */
 
-use super::nfa::NFA;
+use super::charclass::CharClass;
+use super::error::Error;
+use super::nfa;
+use super::nfa::{Config, NFA};
 use super::parser::Parser;
-use super::parser::AST;
+use super::parser::{Lookaround, AST};
 use super::tokenizer::Tokenizer;
+use super::visit::Visitor;
 use rand::prelude::*;
 use rand::{thread_rng, Rng};
 use rand::distributions::Alphanumeric;
 
+// most copies past the minimum that *, + and {n,} will generate
+const UNBOUNDED_EXTRA: usize = 49;
+
 pub struct StringGen {
     rand_strings: Vec<String>,
+    // the string being generated, visiting the AST appends to it
+    rand_string: String,
+    // what each group generated so far, for backreferences to repeat
+    groups: Vec<Option<String>>,
 }
 
 impl StringGen {
     
     // return vec of generated strings and print in main
-    pub fn generate(regex: &str, n: usize) -> Result<Vec<String>, String> {
+    pub fn generate(regex: &str, n: usize) -> Result<Vec<String>, Error> {
+        StringGen::with_config(regex, n, &Config::default())
+    }
+
+    // same, reading the pattern the way config says to
+    pub fn with_config(regex: &str, n: usize, config: &Config) -> Result<Vec<String>, Error> {
         let mut generator = StringGen::new();
-        let ast = &Parser::parse(Tokenizer::new(regex))?;
+        let ast = &Parser::parse_with_flags(config.tokenizer(regex), config.flags())?;
+        nfa::check_size(ast, config.size_limit)?;
 
         while generator.rand_strings.len() < n {
-            generator.rand_strings.push(generator.string_factory(ast));
+            generator.groups = vec![None; nfa::group_count(ast) + 1];
+            let rand_string = generator.string_factory(ast);
+            generator.rand_strings.push(rand_string);
         }
 
         Ok(generator.rand_strings)
@@ -40,72 +59,125 @@ impl StringGen {
     fn new() -> StringGen {
         StringGen {
             rand_strings: vec![],
+            rand_string: String::new(),
+            groups: vec![],
         }
     }
 
     // r e c u r s i v e - d e s c e n t (of random string generation)
-    fn string_factory(&self, ast: &AST) -> String {
-        let mut rand_string = String::new();
+    fn string_factory(&mut self, ast: &AST) -> String {
+        self.rand_string.clear();
+        self.visit(ast);
+        std::mem::take(&mut self.rand_string)
+    }
+
+    // picks a count between the bounds, unbounded repetition gets at most UNBOUNDED_EXTRA extra copies
+    fn rand_repeat(&mut self, ast: &AST, min: usize, max: Option<usize>) {
+        let mut rng = rand::thread_rng();
+        let max = max.unwrap_or(min + UNBOUNDED_EXTRA);
+        let rand_int = rng.gen_range(min, max + 1);
 
-        match ast {
-            AST::AnyChar => self.rand_anychar(&mut rand_string),
-            AST::Char(c) => self.rand_char(&mut rand_string, *c),
-            AST::Catenation(lhs, rhs) => self.rand_catenation(&mut rand_string, lhs, rhs),
-            AST::Alternation(lhs, rhs) => self.rand_alternation(&mut rand_string, lhs, rhs),
-            AST::Closure(clo) => self.rand_closure(&mut rand_string, clo),
-            AST::OneOrMore(plus) => self.rand_plus(&mut rand_string, plus),
+        for i in 0..rand_int {
+            self.visit(ast);
         }
-        rand_string
     }
+}
 
-    fn rand_anychar(&self, rand_string: &mut String) {
+// catenation, lazy and flags keep the visitor's defaults: generating the case
+// as written is always one of the ways to match, and so is being greedy
+impl Visitor for StringGen {
+    fn visit_any_char(&mut self) {
         let mut rng = rand::thread_rng();
-        rand_string.push(rng.sample(Alphanumeric))
+        self.rand_string.push(rng.sample(Alphanumeric))
     }
 
-    fn rand_char(&self, rand_string: &mut String, c: char) {
-        rand_string.push(c);
+    fn visit_char(&mut self, c: char) {
+        self.rand_string.push(c);
     }
 
-    fn rand_catenation(&self, rand_string: &mut String, lhs: &AST, rhs: &AST) {
-        rand_string.push_str(&self.string_factory(&lhs));
-        rand_string.push_str(&self.string_factory(&rhs));
+    fn visit_class(&mut self, class: &CharClass) {
+        let mut rng = rand::thread_rng();
+
+        // negated classes cover most of unicode, so try for something readable first
+        if class.is_negated() {
+            for _ in 0..100 {
+                let c = rng.sample(Alphanumeric);
+                if class.contains(c) {
+                    self.rand_string.push(c);
+                    return;
+                }
+            }
+        }
+
+        let ranges = class.matching_ranges();
+        let total: u32 = ranges.iter().map(|&(lo, hi)| hi as u32 - lo as u32 + 1).sum();
+        if total == 0 {
+            return;
+        }
+        loop {
+            let mut n = rng.gen_range(0, total);
+            for &(lo, hi) in &ranges {
+                let size = hi as u32 - lo as u32 + 1;
+                if n < size {
+                    // ranges can straddle the surrogate gap, those picks get rerolled
+                    if let Some(c) = std::char::from_u32(lo as u32 + n) {
+                        self.rand_string.push(c);
+                        return;
+                    }
+                    break;
+                }
+                n -= size;
+            }
+        }
     }
 
-    fn rand_alternation(&self, rand_string: &mut String, lhs: &AST, rhs: &AST) {
+    fn visit_alternation(&mut self, lhs: &AST, rhs: &AST) {
         let left = rand::random();
         if left {
-            rand_string.push_str(&self.string_factory(&lhs));
+            self.visit(lhs);
         } else {
-            rand_string.push_str(&self.string_factory(&rhs));
+            self.visit(rhs);
         }
     }
 
-    fn rand_closure(&self, rand_string: &mut String, ast: &AST) {
-        let mut rng = rand::thread_rng();
-        let rand_int = rng.gen_range(0, 50);
+    fn visit_closure(&mut self, ast: &AST) {
+        self.rand_repeat(ast, 0, None);
+    }
 
-        for i in 0..rand_int {
-            rand_string.push_str(&self.string_factory(&ast));
-        }
+    fn visit_one_or_more(&mut self, ast: &AST) {
+        self.rand_repeat(ast, 1, None);
     }
 
-    fn rand_plus(&self, rand_string: &mut String, ast: &AST) {
-        let mut rng = rand::thread_rng();
-        let rand_int = rng.gen_range(1, 50);
+    fn visit_optional(&mut self, ast: &AST) {
+        self.rand_repeat(ast, 0, Some(1));
+    }
 
-        for i in 0..rand_int {
-            rand_string.push_str(&self.string_factory(&ast));
-        }
+    fn visit_repeat(&mut self, ast: &AST, min: usize, max: Option<usize>) {
+        self.rand_repeat(ast, min, max);
+    }
 
+    fn visit_group(&mut self, index: usize, name: Option<&str>, ast: &AST) {
+        let start = self.rand_string.len();
+        self.visit(ast);
+        self.groups[index] = Some(self.rand_string[start..].to_string());
     }
 
+    // \N can't match if its group didn't take part, so the empty string is as close as it gets
+    fn visit_backref(&mut self, index: usize) {
+        if let Some(group) = &self.groups[index] {
+            self.rand_string.push_str(group);
+        }
+    }
 
+    // zero-width, and what's around it isn't made to fit
+    fn visit_lookaround(&mut self, kind: Lookaround, ast: &AST) {}
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod string_gen {
     use super::*;
+    use crate::backtrack::Backtrack;
 
     #[test]
     fn rand_anychar() {
@@ -147,6 +219,118 @@ mod string_gen {
         }
     }
 
+    #[test]
+    fn rand_class() {
+        let regex = "[a-c0-9_]+";
+        let nfa = NFA::from(regex).unwrap();
+        let rand_strings = StringGen::generate(regex, 3).unwrap();
+        for string in rand_strings {
+            assert!(nfa.accepts(&string));
+        }
+    }
+
+    #[test]
+    fn rand_negated_class() {
+        let regex = "[^a-zA-Z]+";
+        let nfa = NFA::from(regex).unwrap();
+        let rand_strings = StringGen::generate(regex, 3).unwrap();
+        for string in rand_strings {
+            assert!(nfa.accepts(&string));
+        }
+    }
+
+    #[test]
+    fn rand_perl_classes() {
+        let regex = "\\w\\d\\s\\W\\D\\S";
+        let nfa = NFA::from(regex).unwrap();
+        let rand_strings = StringGen::generate(regex, 10).unwrap();
+        for string in rand_strings {
+            assert!(nfa.accepts(&string));
+        }
+    }
+
+    #[test]
+    fn rand_optional() {
+        let regex = "colou?r";
+        let nfa = NFA::from(regex).unwrap();
+        let rand_strings = StringGen::generate(regex, 5).unwrap();
+        for string in rand_strings {
+            assert!(string == "color" || string == "colour");
+        }
+    }
+
+    #[test]
+    fn rand_lazy() {
+        let regex = "a+?b{1,3}?";
+        let nfa = NFA::from(regex).unwrap();
+        let rand_strings = StringGen::generate(regex, 5).unwrap();
+        for string in rand_strings {
+            assert!(nfa.accepts(&string));
+        }
+    }
+
+    #[test]
+    fn rand_anchors() {
+        let regex = "^[a-z]+$";
+        let nfa = NFA::from(regex).unwrap();
+        let rand_strings = StringGen::generate(regex, 3).unwrap();
+        for string in rand_strings {
+            assert!(nfa.accepts(&string));
+        }
+    }
+
+    #[test]
+    fn rand_repeat() {
+        let regex = "x{2,4}y";
+        let nfa = NFA::from(regex).unwrap();
+        let rand_strings = StringGen::generate(regex, 10).unwrap();
+        for string in rand_strings {
+            assert!(nfa.accepts(&string));
+            assert!(string.len() >= 3 && string.len() <= 5);
+        }
+    }
+
+    #[test]
+    fn rand_exact_repeat() {
+        let rand_strings = StringGen::generate("[a-z]{3}-[0-9]{2,}", 10).unwrap();
+        for string in rand_strings {
+            assert_eq!(string.find('-'), Some(3));
+            assert!(string.len() >= 6);
+        }
+    }
+
+    #[test]
+    fn rand_too_large() {
+        assert!(StringGen::generate("a{1000}{1000}", 1).is_err());
+    }
+
+    #[test]
+    fn rand_backref() {
+        let regex = "(\\w+)-(a|b)\\1\\2";
+        let backtrack = Backtrack::from(regex).unwrap();
+        for string in StringGen::generate(regex, 10).unwrap() {
+            assert_eq!(backtrack.is_match(&string), Ok(true), "{}", string);
+        }
+    }
+
+    #[test]
+    fn rand_empty() {
+        let regex = "^x(|y)(z|)()$";
+        let nfa = NFA::from(regex).unwrap();
+        for string in StringGen::generate(regex, 10).unwrap() {
+            assert!(nfa.is_match(&string), "{}", string);
+        }
+    }
+
+    #[test]
+    fn rand_properties() {
+        let regex = "\\p{Greek}{5} \\p{L}+ [\\p{Nd}\\p{Han}] \\P{L}";
+        let nfa = NFA::from(regex).unwrap();
+        for string in StringGen::generate(regex, 10).unwrap() {
+            assert!(nfa.accepts(&string), "{}", string);
+        }
+    }
+
     #[test]
     fn rand_string1() {
         let regex = "big* chungus*";
@@ -9,22 +9,154 @@ use std::str::Chars;
 #[derive(Debug, PartialEq)]
 pub enum Token {
     LParen,
+    NonCapturing,
+    NamedGroup(String),
+    Flags(String),
+    FlagGroup(String),
+    LookAhead,
+    NotLookAhead,
+    LookBehind,
+    NotLookBehind,
     RParen,
     UnionBar,
     KleeneStar,
     AnyChar,
     Char(char),
     KleenePlus,
+    Question,
+    LBracket,
+    RBracket,
+    Caret,
+    Dollar,
+    Dash,
+    PerlClass(char),
+    PosixClass(String),
+    Property(String),
+    NotProperty(String),
+    WordBoundary,
+    NotWordBoundary,
+    Backref(usize),
+    Repeat(usize, Option<usize>),
+    Error(String),
+}
+
+/**
+ * Byte offsets of a token in the pattern, from start up to but not
+ * including end.
+ */
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /**
+     * The span from the start of self to the end of other.
+     */
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
+/**
+ * The syntax a pattern is written in. Extended is egrep's, and Basic is
+ * POSIX BRE like grep -G, where \\( \\) \\| \\{ \\} \\+ and \\? are the
+ * operators and those chars on their own are literals. Both give the
+ * parser the same tokens.
+ */
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Dialect {
+    Basic,
+    #[default]
+    Extended,
 }
 
 pub struct Tokenizer<'str> {
+    chars: Cursor<'str>,
+    class: Option<ClassPos>,
+    // (?x) is tracked here since it changes how the pattern is split into tokens,
+    // with the setting of each enclosing group kept to be restored at its )
+    verbose: bool,
+    groups: Vec<bool>,
+    dialect: Dialect,
+    // in BRE a * or ^ at the start of an expression is a literal or an anchor
+    expr_start: bool,
+}
+
+/**
+ * Where the tokenizer is inside of a bracket expression, since
+ * ^, ] and - only have special meaning in certain spots.
+ */
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum ClassPos {
+    Open,
+    Negated,
+    Member,
+}
+
+/**
+ * The chars of the pattern along with the byte offset of the next one,
+ * so each token knows where it came from.
+ */
+#[derive(Clone)]
+struct Cursor<'str> {
     chars: Peekable<Chars<'str>>,
+    at: usize,
+}
+
+impl<'str> Iterator for Cursor<'str> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.at += c.len_utf8();
+        Some(c)
+    }
+}
+
+impl<'str> Cursor<'str> {
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
 }
 
 impl<'str> Tokenizer<'str> {
-    pub fn new(input: &'str str) -> Tokenizer {
+    pub fn new(input: &'str str) -> Tokenizer<'str> {
+        Tokenizer {
+            chars: Cursor {
+                chars: input.chars().peekable(),
+                at: 0,
+            },
+            class: None,
+            verbose: false,
+            groups: vec![],
+            dialect: Dialect::Extended,
+            expr_start: true,
+        }
+    }
+
+    /**
+     * Tokenize a pattern that starts out in verbose mode, as if it began
+     * with (?x), when verbose is true.
+     */
+    pub fn with_verbose(input: &'str str, verbose: bool) -> Tokenizer<'str> {
+        Tokenizer::with_dialect(input, Dialect::Extended, verbose)
+    }
+
+    /**
+     * Tokenize a pattern written in the given dialect, starting out in
+     * verbose mode when verbose is true.
+     */
+    pub fn with_dialect(input: &'str str, dialect: Dialect, verbose: bool) -> Tokenizer<'str> {
         Tokenizer {
-            chars: input.chars().peekable(),
+            verbose,
+            dialect,
+            ..Tokenizer::new(input)
         }
     }
 }
@@ -33,19 +165,85 @@ impl<'str> Iterator for Tokenizer<'str> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Token> {
-        self.whitespace();
-        if let Some(c) = self.chars.peek() {
-            Some(match c {
-                '(' | ')' => self.paren(),
-                '|' => self.union(),
-                '*' => self.kleene(),
-                '.' => self.any_char(),
-                '+' => self.kleene_plus(),
-                _ => self.other_chars(),
-            })
-        } else {
-            None
+        self.next_spanned().map(|(token, _)| token)
+    }
+}
+
+/**
+ * The tokens of a pattern paired with their spans, see Tokenizer::spanned.
+ */
+pub struct Spanned<'str> {
+    tokenizer: Tokenizer<'str>,
+}
+
+impl<'str> Iterator for Spanned<'str> {
+    type Item = (Token, Span);
+
+    fn next(&mut self) -> Option<(Token, Span)> {
+        self.tokenizer.next_spanned()
+    }
+}
+
+impl<'str> Tokenizer<'str> {
+    /**
+     * Iterate over the tokens along with where each one is in the pattern.
+     */
+    pub fn spanned(self) -> Spanned<'str> {
+        Spanned { tokenizer: self }
+    }
+
+    /**
+     * The next token and its span. Skipped whitespace and comments are
+     * not part of the span.
+     */
+    pub fn next_spanned(&mut self) -> Option<(Token, Span)> {
+        if self.class.is_none() {
+            self.whitespace();
         }
+        let start = self.chars.at;
+        let token = match (self.class, self.dialect) {
+            (Some(pos), _) => self.class_member(pos)?,
+            (None, Dialect::Basic) => self.basic_token()?,
+            (None, Dialect::Extended) => self.token()?,
+        };
+        Some((token, Span::new(start, self.chars.at)))
+    }
+
+    fn token(&mut self) -> Option<Token> {
+        let c = *self.chars.peek()?;
+        Some(match c {
+            '(' | ')' => self.paren(),
+            '[' => self.bracket(),
+            '|' => self.union(),
+            '*' => self.kleene(),
+            '.' => self.any_char(),
+            '+' => self.kleene_plus(),
+            '?' => self.question(),
+            '^' | '$' => self.anchor(),
+            '{' => self.repeat(),
+            '\\' => self.escape(),
+            _ => self.other_chars(),
+        })
+    }
+
+    // POSIX BRE, where only ., [, \\ and some uses of *, ^ and $ are special
+    fn basic_token(&mut self) -> Option<Token> {
+        let c = *self.chars.peek()?;
+        let start = self.expr_start;
+        self.expr_start = false;
+        Some(match c {
+            '[' => self.bracket(),
+            '.' => self.any_char(),
+            '*' if !start => self.kleene(),
+            // a ^ anchor still counts as the start, so ^* is a literal *
+            '^' if start => {
+                self.expr_start = true;
+                self.anchor()
+            }
+            '$' if self.at_expr_end() => self.anchor(),
+            '\\' => self.basic_escape(start),
+            _ => self.other_chars(),
+        })
     }
 }
 
@@ -61,6 +259,21 @@ mod tokenizer {
         assert_eq!(tokens.next(), None);
     }
 
+    #[test]
+    fn spans() {
+        let mut tokens = Tokenizer::with_verbose("é\\d{2,3} [^a-b] # c", true).spanned();
+        assert_eq!(tokens.next(), Some((Token::Char('é'), Span::new(0, 2))));
+        assert_eq!(tokens.next(), Some((Token::PerlClass('d'), Span::new(2, 4))));
+        assert_eq!(tokens.next(), Some((Token::Repeat(2, Some(3)), Span::new(4, 9))));
+        assert_eq!(tokens.next(), Some((Token::LBracket, Span::new(10, 11))));
+        assert_eq!(tokens.next(), Some((Token::Caret, Span::new(11, 12))));
+        assert_eq!(tokens.next(), Some((Token::Char('a'), Span::new(12, 13))));
+        assert_eq!(tokens.next(), Some((Token::Dash, Span::new(13, 14))));
+        assert_eq!(tokens.next(), Some((Token::Char('b'), Span::new(14, 15))));
+        assert_eq!(tokens.next(), Some((Token::RBracket, Span::new(15, 16))));
+        assert_eq!(tokens.next(), None);
+    }
+
     #[test]
     fn parentheses_w_chars() {
         let mut tokens = Tokenizer::new("(yuh)");
@@ -106,6 +319,56 @@ mod tokenizer {
         assert_eq!(tokens.next(), None);
     }
 
+    #[test]
+    fn question() {
+        let mut tokens = Tokenizer::new("a?b*?");
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::Question));
+        assert_eq!(tokens.next(), Some(Token::Char('b')));
+        assert_eq!(tokens.next(), Some(Token::KleeneStar));
+        assert_eq!(tokens.next(), Some(Token::Question));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn anchors() {
+        let mut tokens = Tokenizer::new("^a$[$^]");
+        assert_eq!(tokens.next(), Some(Token::Caret));
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::Dollar));
+        assert_eq!(tokens.next(), Some(Token::LBracket));
+        assert_eq!(tokens.next(), Some(Token::Char('$')));
+        assert_eq!(tokens.next(), Some(Token::Char('^')));
+        assert_eq!(tokens.next(), Some(Token::RBracket));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn repeats() {
+        let mut tokens = Tokenizer::new("a{3}b{2,}c{1,4}");
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::Repeat(3, Some(3))));
+        assert_eq!(tokens.next(), Some(Token::Char('b')));
+        assert_eq!(tokens.next(), Some(Token::Repeat(2, None)));
+        assert_eq!(tokens.next(), Some(Token::Char('c')));
+        assert_eq!(tokens.next(), Some(Token::Repeat(1, Some(4))));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn literal_braces() {
+        let mut tokens = Tokenizer::new("{x}{,2}{");
+        assert_eq!(tokens.next(), Some(Token::Char('{')));
+        assert_eq!(tokens.next(), Some(Token::Char('x')));
+        assert_eq!(tokens.next(), Some(Token::Char('}')));
+        assert_eq!(tokens.next(), Some(Token::Char('{')));
+        assert_eq!(tokens.next(), Some(Token::Char(',')));
+        assert_eq!(tokens.next(), Some(Token::Char('2')));
+        assert_eq!(tokens.next(), Some(Token::Char('}')));
+        assert_eq!(tokens.next(), Some(Token::Char('{')));
+        assert_eq!(tokens.next(), None);
+    }
+
     #[test]
     fn all_tokens() {
         let mut tokens = Tokenizer::new("(a|b).c*+");
@@ -120,63 +383,739 @@ mod tokenizer {
         assert_eq!(tokens.next(), Some(Token::KleenePlus));
         assert_eq!(tokens.next(), None);
     }
+
+    #[test]
+    fn escaped_metachars() {
+        let mut tokens = Tokenizer::new("\\(\\)\\|\\*\\.\\+\\\\");
+        assert_eq!(tokens.next(), Some(Token::Char('(')));
+        assert_eq!(tokens.next(), Some(Token::Char(')')));
+        assert_eq!(tokens.next(), Some(Token::Char('|')));
+        assert_eq!(tokens.next(), Some(Token::Char('*')));
+        assert_eq!(tokens.next(), Some(Token::Char('.')));
+        assert_eq!(tokens.next(), Some(Token::Char('+')));
+        assert_eq!(tokens.next(), Some(Token::Char('\\')));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn control_escapes() {
+        let mut tokens = Tokenizer::new("\\t\\n\\x41\\u{3bb}\\u{1F600}");
+        assert_eq!(tokens.next(), Some(Token::Char('\t')));
+        assert_eq!(tokens.next(), Some(Token::Char('\n')));
+        assert_eq!(tokens.next(), Some(Token::Char('A')));
+        assert_eq!(tokens.next(), Some(Token::Char('λ')));
+        assert_eq!(tokens.next(), Some(Token::Char('😀')));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn basic_dialect() {
+        let mut tokens = Tokenizer::with_dialect("\\(ab\\)*\\|c\\{2,3\\}+?{|\\+", Dialect::Basic, false);
+        assert_eq!(tokens.next(), Some(Token::LParen));
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::Char('b')));
+        assert_eq!(tokens.next(), Some(Token::RParen));
+        assert_eq!(tokens.next(), Some(Token::KleeneStar));
+        assert_eq!(tokens.next(), Some(Token::UnionBar));
+        assert_eq!(tokens.next(), Some(Token::Char('c')));
+        assert_eq!(tokens.next(), Some(Token::Repeat(2, Some(3))));
+        assert_eq!(tokens.next(), Some(Token::Char('+')));
+        assert_eq!(tokens.next(), Some(Token::Char('?')));
+        assert_eq!(tokens.next(), Some(Token::Char('{')));
+        assert_eq!(tokens.next(), Some(Token::Char('|')));
+        assert_eq!(tokens.next(), Some(Token::KleenePlus));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn basic_expression_start() {
+        let mut tokens = Tokenizer::with_dialect("*a\\(*b\\|^*\\)^$$\\{", Dialect::Basic, false);
+        assert_eq!(tokens.next(), Some(Token::Char('*')));
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::LParen));
+        assert_eq!(tokens.next(), Some(Token::Char('*')));
+        assert_eq!(tokens.next(), Some(Token::Char('b')));
+        assert_eq!(tokens.next(), Some(Token::UnionBar));
+        assert_eq!(tokens.next(), Some(Token::Caret));
+        assert_eq!(tokens.next(), Some(Token::Char('*')));
+        assert_eq!(tokens.next(), Some(Token::RParen));
+        assert_eq!(tokens.next(), Some(Token::Char('^')));
+        assert_eq!(tokens.next(), Some(Token::Char('$')));
+        assert_eq!(tokens.next(), Some(Token::Char('$')));
+        assert!(matches!(tokens.next(), Some(Token::Error(_))));
+
+        let mut tokens = Tokenizer::with_dialect("\\(a$\\)\\1\\w[*\\]", Dialect::Basic, false);
+        assert_eq!(tokens.next(), Some(Token::LParen));
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::Dollar));
+        assert_eq!(tokens.next(), Some(Token::RParen));
+        assert_eq!(tokens.next(), Some(Token::Backref(1)));
+        assert_eq!(tokens.next(), Some(Token::PerlClass('w')));
+        assert_eq!(tokens.next(), Some(Token::LBracket));
+        assert_eq!(tokens.next(), Some(Token::Char('*')));
+        assert_eq!(tokens.next(), Some(Token::Char(']')));
+    }
+
+    #[test]
+    fn basic_intervals() {
+        let mut tokens = Tokenizer::with_dialect("a\\{3\\}b\\{1,\\}\\{2", Dialect::Basic, false);
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::Repeat(3, Some(3))));
+        assert_eq!(tokens.next(), Some(Token::Char('b')));
+        assert_eq!(tokens.next(), Some(Token::Repeat(1, None)));
+        assert!(matches!(tokens.next(), Some(Token::Error(_))));
+        assert!(matches!(Tokenizer::with_dialect("a\\{x\\}", Dialect::Basic, false).nth(1), Some(Token::Error(_))));
+    }
+
+    #[test]
+    fn perl_classes() {
+        let mut tokens = Tokenizer::new("\\d\\W[\\s_]");
+        assert_eq!(tokens.next(), Some(Token::PerlClass('d')));
+        assert_eq!(tokens.next(), Some(Token::PerlClass('W')));
+        assert_eq!(tokens.next(), Some(Token::LBracket));
+        assert_eq!(tokens.next(), Some(Token::PerlClass('s')));
+        assert_eq!(tokens.next(), Some(Token::Char('_')));
+        assert_eq!(tokens.next(), Some(Token::RBracket));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn properties() {
+        let mut tokens = Tokenizer::new("\\pL\\p{Greek}\\P{Nd}\\p{^sc=Han}[\\p{Lu}]");
+        assert_eq!(tokens.next(), Some(Token::Property(String::from("L"))));
+        assert_eq!(tokens.next(), Some(Token::Property(String::from("Greek"))));
+        assert_eq!(tokens.next(), Some(Token::NotProperty(String::from("Nd"))));
+        assert_eq!(tokens.next(), Some(Token::NotProperty(String::from("sc=Han"))));
+        assert_eq!(tokens.next(), Some(Token::LBracket));
+        assert_eq!(tokens.next(), Some(Token::Property(String::from("Lu"))));
+        assert_eq!(tokens.next(), Some(Token::RBracket));
+        assert_eq!(tokens.next(), None);
+        assert!(matches!(Tokenizer::new("\\p{Greek").next(), Some(Token::Error(_))));
+        assert!(matches!(Tokenizer::new("\\p").next(), Some(Token::Error(_))));
+    }
+
+    #[test]
+    fn word_boundaries() {
+        let mut tokens = Tokenizer::new("\\bid\\B");
+        assert_eq!(tokens.next(), Some(Token::WordBoundary));
+        assert_eq!(tokens.next(), Some(Token::Char('i')));
+        assert_eq!(tokens.next(), Some(Token::Char('d')));
+        assert_eq!(tokens.next(), Some(Token::NotWordBoundary));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn backrefs() {
+        let mut tokens = Tokenizer::new("(a)\\1\\9\\0");
+        assert_eq!(tokens.next(), Some(Token::LParen));
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::RParen));
+        assert_eq!(tokens.next(), Some(Token::Backref(1)));
+        assert_eq!(tokens.next(), Some(Token::Backref(9)));
+        assert_eq!(tokens.next(), Some(Token::Char('\0')));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn bracket_class() {
+        let mut tokens = Tokenizer::new("[a-z_]*");
+        assert_eq!(tokens.next(), Some(Token::LBracket));
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::Dash));
+        assert_eq!(tokens.next(), Some(Token::Char('z')));
+        assert_eq!(tokens.next(), Some(Token::Char('_')));
+        assert_eq!(tokens.next(), Some(Token::RBracket));
+        assert_eq!(tokens.next(), Some(Token::KleeneStar));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn posix_classes() {
+        let mut tokens = Tokenizer::new("[[:alpha:]_[:digit:]][[:]");
+        assert_eq!(tokens.next(), Some(Token::LBracket));
+        assert_eq!(tokens.next(), Some(Token::PosixClass(String::from("alpha"))));
+        assert_eq!(tokens.next(), Some(Token::Char('_')));
+        assert_eq!(tokens.next(), Some(Token::PosixClass(String::from("digit"))));
+        assert_eq!(tokens.next(), Some(Token::RBracket));
+        assert_eq!(tokens.next(), Some(Token::LBracket));
+        assert_eq!(tokens.next(), Some(Token::Char('[')));
+        assert_eq!(tokens.next(), Some(Token::Char(':')));
+        assert_eq!(tokens.next(), Some(Token::RBracket));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn bracket_literals() {
+        let mut tokens = Tokenizer::new("[^]^*(-]");
+        assert_eq!(tokens.next(), Some(Token::LBracket));
+        assert_eq!(tokens.next(), Some(Token::Caret));
+        assert_eq!(tokens.next(), Some(Token::Char(']')));
+        assert_eq!(tokens.next(), Some(Token::Char('^')));
+        assert_eq!(tokens.next(), Some(Token::Char('*')));
+        assert_eq!(tokens.next(), Some(Token::Char('(')));
+        assert_eq!(tokens.next(), Some(Token::Char('-')));
+        assert_eq!(tokens.next(), Some(Token::RBracket));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn class_escapes() {
+        let mut tokens = Tokenizer::new("[\\b]\\b[a\\1]");
+        assert_eq!(tokens.next(), Some(Token::LBracket));
+        assert_eq!(tokens.next(), Some(Token::Char('\u{08}')));
+        assert_eq!(tokens.next(), Some(Token::RBracket));
+        assert_eq!(tokens.next(), Some(Token::WordBoundary));
+        assert_eq!(tokens.next(), Some(Token::LBracket));
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(
+            tokens.next(),
+            Some(Token::Error(String::from("Backreference \\1 can't be used inside a character class")))
+        );
+        assert_eq!(tokens.next(), Some(Token::RBracket));
+
+        let mut tokens = Tokenizer::new("[\\0\\t\\d\\]]");
+        assert_eq!(tokens.next(), Some(Token::LBracket));
+        assert_eq!(tokens.next(), Some(Token::Char('\0')));
+        assert_eq!(tokens.next(), Some(Token::Char('\t')));
+        assert_eq!(tokens.next(), Some(Token::PerlClass('d')));
+        assert_eq!(tokens.next(), Some(Token::Char(']')));
+        assert_eq!(tokens.next(), Some(Token::RBracket));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn bad_escapes() {
+        assert!(matches!(Tokenizer::new("\\q").next(), Some(Token::Error(_))));
+        assert!(matches!(Tokenizer::new("\\").next(), Some(Token::Error(_))));
+        assert!(matches!(Tokenizer::new("\\x4").next(), Some(Token::Error(_))));
+        assert!(matches!(Tokenizer::new("\\u{110000}").next(), Some(Token::Error(_))));
+        assert!(matches!(Tokenizer::new("\\u{}").next(), Some(Token::Error(_))));
+    }
+
+    #[test]
+    fn group_syntax() {
+        let mut tokens = Tokenizer::new("(?P<year>\\d)(?:a)(b)");
+        assert_eq!(tokens.next(), Some(Token::NamedGroup(String::from("year"))));
+        assert_eq!(tokens.next(), Some(Token::PerlClass('d')));
+        assert_eq!(tokens.next(), Some(Token::RParen));
+        assert_eq!(tokens.next(), Some(Token::NonCapturing));
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::RParen));
+        assert_eq!(tokens.next(), Some(Token::LParen));
+    }
+
+    #[test]
+    fn flags() {
+        let mut tokens = Tokenizer::new("(?i)(?s-m:a)(?-i)");
+        assert_eq!(tokens.next(), Some(Token::Flags(String::from("i"))));
+        assert_eq!(tokens.next(), Some(Token::FlagGroup(String::from("s-m"))));
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::RParen));
+        assert_eq!(tokens.next(), Some(Token::Flags(String::from("-i"))));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn bad_flags() {
+        assert!(matches!(Tokenizer::new("(?q)").next(), Some(Token::Error(_))));
+        assert!(matches!(Tokenizer::new("(?ii)").next(), Some(Token::Error(_))));
+        assert!(matches!(Tokenizer::new("(?i-)").next(), Some(Token::Error(_))));
+        assert!(matches!(Tokenizer::new("(?i-s-m)").next(), Some(Token::Error(_))));
+        assert!(matches!(Tokenizer::new("(?i").next(), Some(Token::Error(_))));
+    }
+
+    #[test]
+    fn verbose() {
+        let mut tokens = Tokenizer::new("a(?x: b # comment\n c)d [ ]\\ ");
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::FlagGroup(String::from("x"))));
+        assert_eq!(tokens.next(), Some(Token::Char('b')));
+        assert_eq!(tokens.next(), Some(Token::Char('c')));
+        assert_eq!(tokens.next(), Some(Token::RParen));
+        assert_eq!(tokens.next(), Some(Token::Char('d')));
+        assert_eq!(tokens.next(), Some(Token::Char(' ')));
+        assert_eq!(tokens.next(), Some(Token::LBracket));
+        assert_eq!(tokens.next(), Some(Token::Char(' ')));
+        assert_eq!(tokens.next(), Some(Token::RBracket));
+        assert_eq!(tokens.next(), Some(Token::Char(' ')));
+        assert_eq!(tokens.next(), None);
+
+        let mut tokens = Tokenizer::new("(?x) a (?-x) b");
+        assert_eq!(tokens.next(), Some(Token::Flags(String::from("x"))));
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::Flags(String::from("-x"))));
+        assert_eq!(tokens.next(), Some(Token::Char(' ')));
+        assert_eq!(tokens.next(), Some(Token::Char('b')));
+
+        let mut tokens = Tokenizer::with_verbose("a b # comment\n(?-x: c)", true);
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::Char('b')));
+        assert_eq!(tokens.next(), Some(Token::FlagGroup(String::from("-x"))));
+        assert_eq!(tokens.next(), Some(Token::Char(' ')));
+        assert_eq!(tokens.next(), Some(Token::Char('c')));
+    }
+
+    #[test]
+    fn significant_whitespace() {
+        let mut tokens = Tokenizer::new("a\tb\n #");
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::Char('\t')));
+        assert_eq!(tokens.next(), Some(Token::Char('b')));
+        assert_eq!(tokens.next(), Some(Token::Char('\n')));
+        assert_eq!(tokens.next(), Some(Token::Char(' ')));
+        assert_eq!(tokens.next(), Some(Token::Char('#')));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn lookarounds() {
+        let mut tokens = Tokenizer::new("(?=a)(?!b)(?<=c)(?<!d)");
+        assert_eq!(tokens.next(), Some(Token::LookAhead));
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::RParen));
+        assert_eq!(tokens.next(), Some(Token::NotLookAhead));
+        assert_eq!(tokens.next(), Some(Token::Char('b')));
+        assert_eq!(tokens.next(), Some(Token::RParen));
+        assert_eq!(tokens.next(), Some(Token::LookBehind));
+        assert_eq!(tokens.next(), Some(Token::Char('c')));
+        assert_eq!(tokens.next(), Some(Token::RParen));
+        assert_eq!(tokens.next(), Some(Token::NotLookBehind));
+        assert!(matches!(Tokenizer::new("(?<x>a)").next(), Some(Token::Error(_))));
+    }
+
+    #[test]
+    fn bad_group_syntax() {
+        assert!(matches!(Tokenizer::new("(?P<1a>x)").next(), Some(Token::Error(_))));
+        assert!(matches!(Tokenizer::new("(?P<>x)").next(), Some(Token::Error(_))));
+        assert!(matches!(Tokenizer::new("(?P<name").next(), Some(Token::Error(_))));
+        assert!(matches!(Tokenizer::new("(?%x)").next(), Some(Token::Error(_))));
+        assert!(matches!(Tokenizer::new("(?").next(), Some(Token::Error(_))));
+    }
 }
 
+/**
+ * The inline flags: case insensitive, . matches \n, ^ and $ match at
+ * line breaks, and verbose mode.
+ */
+pub const FLAGS: &str = "imsx";
+
 // helper methods for each token below
 impl<'str> Tokenizer<'str> {
+    // whitespace is only skipped in verbose mode, where comments run to the end of the line
     fn whitespace(&mut self) {
-        while let Some(c) = self.chars.peek() {
-            match c {
-                '\t' | '\n' => self.chars.next(),
+        while self.verbose {
+            match self.chars.peek() {
+                Some(c) if c.is_whitespace() => self.chars.next(),
+                Some('#') => self.chars.find(|&c| c == '\n'),
                 _ => break,
             };
         }
     }
 
+    // the helpers below are only called with their char next, which they consume
     fn paren(&mut self) -> Token {
-        let c = self.chars.next().unwrap();
-        match c {
-            '(' => Token::LParen,
-            ')' => Token::RParen,
-            _ => panic!("Expected parentheses"),
+        match self.chars.next() {
+            Some('(') => {
+                self.groups.push(self.verbose);
+                match self.chars.peek() {
+                    Some('?') => self.group_syntax(),
+                    _ => Token::LParen,
+                }
+            }
+            _ => {
+                if let Some(verbose) = self.groups.pop() {
+                    self.verbose = verbose;
+                }
+                Token::RParen
+            }
         }
     }
 
-    fn union(&mut self) -> Token {
-        let c = self.chars.next().unwrap();
-        if c != '|' {
-            panic!("Expected union bar");
+    // (?:...), (?P<name>...), (?flags), (?flags:...) and the lookarounds
+    // (?=...), (?!...), (?<=...) and (?<!...), with the ( already consumed
+    fn group_syntax(&mut self) -> Token {
+        self.chars.next();
+        match self.chars.peek() {
+            Some(':') => {
+                self.chars.next();
+                Token::NonCapturing
+            }
+            Some('=') => {
+                self.chars.next();
+                Token::LookAhead
+            }
+            Some('!') => {
+                self.chars.next();
+                Token::NotLookAhead
+            }
+            Some('<') => {
+                self.chars.next();
+                match self.chars.next() {
+                    Some('=') => Token::LookBehind,
+                    Some('!') => Token::NotLookBehind,
+                    _ => Token::Error(String::from("Unknown group syntax: (?<, named groups are written (?P<name>...)")),
+                }
+            }
+            Some('P') => {
+                self.chars.next();
+                match self.chars.next() {
+                    Some('<') => self.group_name(),
+                    _ => Token::Error(String::from("Unknown group syntax: (?P")),
+                }
+            }
+            Some(&c) if FLAGS.contains(c) || c == '-' => self.flags(),
+            Some(&c) => Token::Error(format!("Unknown group syntax: (?{}", c)),
+            None => Token::Error(String::from("Unexpected end of input after (?")),
         }
+    }
+
+    // imsx, optionally followed by - and the flags to turn off, up to a ) or :
+    fn flags(&mut self) -> Token {
+        let mut spec = String::new();
+        loop {
+            match self.chars.next() {
+                Some(c) if FLAGS.contains(c) && !spec.contains(c) => spec.push(c),
+                Some('-') if !spec.contains('-') => spec.push('-'),
+                Some(end @ ')') | Some(end @ ':') if !spec.is_empty() && !spec.ends_with('-') => {
+                    let enabled = spec.split('-').next().unwrap_or_default();
+                    if enabled.contains('x') {
+                        self.verbose = true;
+                    } else if spec.contains('x') {
+                        self.verbose = false;
+                    }
+                    if end == ':' {
+                        return Token::FlagGroup(spec);
+                    }
+                    // (?flags) is not a group of its own, it changes the one it is in
+                    self.groups.pop();
+                    return Token::Flags(spec);
+                }
+                Some(c) => return Token::Error(format!("Invalid flags: (?{}{}", spec, c)),
+                None => return Token::Error(format!("Unterminated flags: (?{}", spec)),
+            }
+        }
+    }
+
+    // name> - a letter or _ followed by letters, digits and _
+    fn group_name(&mut self) -> Token {
+        let mut name = String::new();
+        loop {
+            match self.chars.next() {
+                Some('>') if !name.is_empty() => return Token::NamedGroup(name),
+                Some(c) if c == '_' || c.is_alphabetic() => name.push(c),
+                Some(c) if c.is_ascii_digit() && !name.is_empty() => name.push(c),
+                Some(c) => return Token::Error(format!("Invalid group name: (?P<{}{}", name, c)),
+                None => return Token::Error(format!("Unterminated group name: (?P<{}", name)),
+            }
+        }
+    }
+
+    fn union(&mut self) -> Token {
+        self.chars.next();
         Token::UnionBar
     }
 
     fn kleene(&mut self) -> Token {
-        let c = self.chars.next().unwrap();
-        if c != '*' {
-            panic!("Expected Kleene star");
-        }
+        self.chars.next();
         Token::KleeneStar
     }
 
     fn any_char(&mut self) -> Token {
-        let c = self.chars.next().unwrap();
-        if c != '.' {
-            panic!("Expected any char");
-        }
+        self.chars.next();
         Token::AnyChar
     }
     
     fn kleene_plus(&mut self) -> Token {
-        let c = self.chars.next().unwrap();
-        if c != '+' {
-            panic!("Expected Kleene plus");
-        }
+        self.chars.next();
         Token::KleenePlus
     }
 
+    fn anchor(&mut self) -> Token {
+        match self.chars.next() {
+            Some('^') => Token::Caret,
+            _ => Token::Dollar,
+        }
+    }
+
+    fn question(&mut self) -> Token {
+        self.chars.next();
+        Token::Question
+    }
+
+    // {n}, {n,} or {n,m}; a { that doesn't start one of these is a literal
+    fn repeat(&mut self) -> Token {
+        let mut ahead = self.chars.clone();
+        ahead.next();
+        let mut min = String::new();
+        let mut max = String::new();
+        let mut comma = false;
+        loop {
+            match ahead.next() {
+                Some(c) if c.is_ascii_digit() && !comma => min.push(c),
+                Some(c) if c.is_ascii_digit() => max.push(c),
+                Some(',') if !comma && !min.is_empty() => comma = true,
+                Some('}') if !min.is_empty() => break,
+                _ => return self.other_chars(),
+            }
+        }
+        self.chars = ahead;
+        repeat_count(&min, comma, &max)
+    }
+
+    // the BRE \\( \\) \\| \\{ \\+ and \\?, where the ones that repeat something
+    // are literals at the start of an expression like a bare * is
+    fn basic_escape(&mut self, start: bool) -> Token {
+        let mut ahead = self.chars.clone();
+        ahead.next();
+        let token = match ahead.next() {
+            Some('(') => {
+                self.groups.push(self.verbose);
+                self.expr_start = true;
+                Token::LParen
+            }
+            Some(')') => {
+                if let Some(verbose) = self.groups.pop() {
+                    self.verbose = verbose;
+                }
+                Token::RParen
+            }
+            Some('|') => {
+                self.expr_start = true;
+                Token::UnionBar
+            }
+            Some('{') if !start => {
+                self.chars = ahead;
+                return self.interval();
+            }
+            Some('+') if !start => Token::KleenePlus,
+            Some('?') if !start => Token::Question,
+            _ => return self.escape(),
+        };
+        self.chars = ahead;
+        token
+    }
+
+    // in BRE $ is only an anchor at the end of an expression
+    fn at_expr_end(&self) -> bool {
+        let mut ahead = self.chars.clone();
+        ahead.next();
+        match ahead.next() {
+            None => true,
+            Some('\\') => matches!(ahead.next(), Some(')') | Some('|')),
+            Some(_) => false,
+        }
+    }
+
+    // n\\}, n,\\} or n,m\\} after a BRE \\{, which unlike a { in ERE is
+    // never taken literally
+    fn interval(&mut self) -> Token {
+        let mut min = String::new();
+        let mut max = String::new();
+        let mut comma = false;
+        loop {
+            match self.chars.next() {
+                Some(c) if c.is_ascii_digit() && !comma => min.push(c),
+                Some(c) if c.is_ascii_digit() => max.push(c),
+                Some(',') if !comma && !min.is_empty() => comma = true,
+                Some('\\') if !min.is_empty() && self.chars.peek() == Some(&'}') => {
+                    self.chars.next();
+                    return repeat_count(&min, comma, &max);
+                }
+                _ => return Token::Error(String::from("Invalid interval, expected \\{n\\}, \\{n,\\} or \\{n,m\\}")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Token {
+        self.chars.next();
+        match self.chars.next() {
+            Some('t') => Token::Char('\t'),
+            Some('n') => Token::Char('\n'),
+            Some('r') => Token::Char('\r'),
+            Some('f') => Token::Char('\u{0C}'),
+            Some('v') => Token::Char('\u{0B}'),
+            Some('0') => Token::Char('\0'),
+            Some('x') => self.hex_escape(),
+            Some('u') => self.unicode_escape(),
+            Some(c @ 'd') | Some(c @ 'w') | Some(c @ 's') => Token::PerlClass(c),
+            Some(c @ 'D') | Some(c @ 'W') | Some(c @ 'S') => Token::PerlClass(c),
+            Some('p') => self.property(false),
+            Some('P') => self.property(true),
+            Some('b') => Token::WordBoundary,
+            Some('B') => Token::NotWordBoundary,
+            Some(c @ '1'..='9') => Token::Backref(c as usize - '0' as usize),
+            // any escaped metacharacter or punctuation is taken literally
+            Some(c) if !c.is_alphanumeric() => Token::Char(c),
+            Some(c) => Token::Error(format!("Unknown escape sequence: \\{}", c)),
+            None => Token::Error(String::from("Trailing backslash at end of pattern")),
+        }
+    }
+
+    // \pL or \p{name}, where \p{^name} is the negation like \P{name} is
+    fn property(&mut self, negated: bool) -> Token {
+        let name = match self.chars.next() {
+            Some('{') => {
+                let mut name = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Token::Error(format!("Unterminated Unicode property: \\p{{{}", name)),
+                    }
+                }
+                name
+            }
+            Some(c) if c.is_ascii_alphabetic() => c.to_string(),
+            _ => return Token::Error(String::from("Expected a property name after \\p")),
+        };
+        let (negated, name) = match name.strip_prefix('^') {
+            Some(name) => (!negated, name.to_string()),
+            None => (negated, name),
+        };
+        if negated {
+            Token::NotProperty(name)
+        } else {
+            Token::Property(name)
+        }
+    }
+
+    // \xNN - exactly two hex digits
+    fn hex_escape(&mut self) -> Token {
+        let mut digits = String::new();
+        for _ in 0..2 {
+            match self.chars.peek() {
+                Some(&c) if c.is_ascii_hexdigit() => {
+                    self.chars.next();
+                    digits.push(c)
+                }
+                _ => return Token::Error(format!("Expected two hex digits after \\x, found \\x{}", digits)),
+            }
+        }
+        match u8::from_str_radix(&digits, 16) {
+            Ok(byte) => Token::Char(byte as char),
+            Err(_) => Token::Error(format!("Expected two hex digits after \\x, found \\x{}", digits)),
+        }
+    }
+
+    // \u{N...} - one to six hex digits naming a unicode scalar value
+    fn unicode_escape(&mut self) -> Token {
+        if self.chars.next() != Some('{') {
+            return Token::Error(String::from("Expected { after \\u"));
+        }
+        let mut digits = String::new();
+        loop {
+            match self.chars.next() {
+                Some('}') => break,
+                Some(c) if c.is_ascii_hexdigit() && digits.len() < 6 => digits.push(c),
+                _ => return Token::Error(String::from("Invalid unicode escape, expected \\u{N...} with 1 to 6 hex digits")),
+            }
+        }
+        match u32::from_str_radix(&digits, 16).ok().and_then(std::char::from_u32) {
+            Some(c) => Token::Char(c),
+            None => Token::Error(format!("Invalid unicode scalar value: \\u{{{}}}", digits)),
+        }
+    }
+
+    fn bracket(&mut self) -> Token {
+        self.chars.next();
+        self.class = Some(ClassPos::Open);
+        Token::LBracket
+    }
+
+    // inside [...] everything is a literal except a leading ^, a closing ]
+    // that is not the first member, and a - between two members
+    fn class_member(&mut self, pos: ClassPos) -> Option<Token> {
+        let c = *self.chars.peek()?;
+        let first = pos != ClassPos::Member;
+        self.class = Some(ClassPos::Member);
+        Some(match c {
+            '^' if pos == ClassPos::Open => {
+                self.chars.next();
+                self.class = Some(ClassPos::Negated);
+                Token::Caret
+            }
+            ']' if !first => {
+                self.chars.next();
+                self.class = None;
+                Token::RBracket
+            }
+            '-' if !first => {
+                self.chars.next();
+                match self.chars.peek() {
+                    Some(']') => Token::Char('-'),
+                    _ => Token::Dash,
+                }
+            }
+            '[' => self.posix_class(),
+            '\\' => self.class_escape(),
+            _ => self.other_chars(),
+        })
+    }
+
+    // escapes in a class are the same as outside of one, except the ones
+    // that only make sense between chars: \b is a backspace there, as in
+    // every other dialect, and there is no group for \1 to refer to
+    fn class_escape(&mut self) -> Token {
+        let mut ahead = self.chars.clone();
+        ahead.next();
+        match ahead.next() {
+            Some('b') => {
+                self.chars = ahead;
+                Token::Char('\u{08}')
+            }
+            Some(c @ '1'..='9') => {
+                self.chars = ahead;
+                Token::Error(format!("Backreference \\{} can't be used inside a character class", c))
+            }
+            _ => self.escape(),
+        }
+    }
+
+    // [:name:] inside of a bracket expression, otherwise [ is just a literal
+    fn posix_class(&mut self) -> Token {
+        let mut ahead = self.chars.clone();
+        ahead.next();
+        if ahead.next() != Some(':') {
+            return self.other_chars();
+        }
+        let mut name = String::new();
+        while let Some(c) = ahead.next() {
+            match c {
+                ':' if ahead.peek() == Some(&']') => {
+                    ahead.next();
+                    self.chars = ahead;
+                    return Token::PosixClass(name);
+                }
+                ']' => break,
+                c => name.push(c),
+            }
+        }
+        self.other_chars()
+    }
+
     fn other_chars(&mut self) -> Token {
-        let c = self.chars.next().unwrap();
-        Token::Char(c)
+        match self.chars.next() {
+            Some(c) => Token::Char(c),
+            None => Token::Error(String::from("Unexpected end of input")),
+        }
+    }
+}
+
+// the Repeat for the digits of {min} or {min,max}, where max may be empty
+fn repeat_count(min: &str, comma: bool, max: &str) -> Token {
+    let min_count = match min.parse() {
+        Ok(n) => n,
+        Err(_) => return Token::Error(format!("Repetition count is too large: {{{}}}", min)),
+    };
+    if !comma {
+        Token::Repeat(min_count, Some(min_count))
+    } else if max.is_empty() {
+        Token::Repeat(min_count, None)
+    } else {
+        match max.parse() {
+            Ok(n) => Token::Repeat(min_count, Some(n)),
+            Err(_) => Token::Error(format!("Repetition count is too large: {{{},{}}}", min, max)),
+        }
     }
 }
 
@@ -0,0 +1,330 @@
+/**
+ * Tar Heel egrep - unicode tables
+ *
+ * The range tables are generated by build.rs out of the UCD files in
+ * data/ucd; this module turns them into CharClasses for the parser.
+ */
+use super::charclass::CharClass;
+use std::sync::OnceLock;
+
+mod tables {
+    include!(concat!(env!("OUT_DIR"), "/unicode_tables.rs"));
+}
+
+/**
+ * Look up a General_Category by its short name, e.g. Lu or Nd. A single
+ * letter like L or N is the union of every category in that group.
+ */
+pub fn general_category(name: &str) -> Option<CharClass> {
+    if name.len() == 1 {
+        let mut class = CharClass::new();
+        let mut found = false;
+        for (_, ranges) in tables::GENERAL_CATEGORY
+            .iter()
+            .filter(|(category, _)| category.starts_with(name))
+        {
+            class.push_ranges(ranges);
+            found = true;
+        }
+        return if found { Some(class) } else { None };
+    }
+    lookup(tables::GENERAL_CATEGORY, name).map(CharClass::from_ranges)
+}
+
+/**
+ * Look up a binary property. Only Alphabetic, White_Space and
+ * Join_Control are vendored in data/ucd, so others like Uppercase or
+ * Emoji aren't known.
+ */
+pub fn binary_property(name: &str) -> Option<CharClass> {
+    lookup(tables::BINARY_PROPERTY, name).map(CharClass::from_ranges)
+}
+
+/**
+ * Look up a Script by its long name, e.g. Greek or Old_Italic.
+ */
+pub fn script(name: &str) -> Option<CharClass> {
+    lookup(tables::SCRIPT, name).map(CharClass::from_ranges)
+}
+
+/**
+ * The long names of the General_Category values, so \p{Letter} works
+ * as well as \p{L}.
+ */
+const CATEGORY_ALIASES: &[(&str, &str)] = &[
+    ("Letter", "L"),
+    ("Cased_Letter", "LC"),
+    ("Uppercase_Letter", "Lu"),
+    ("Lowercase_Letter", "Ll"),
+    ("Titlecase_Letter", "Lt"),
+    ("Modifier_Letter", "Lm"),
+    ("Other_Letter", "Lo"),
+    ("Mark", "M"),
+    ("Nonspacing_Mark", "Mn"),
+    ("Spacing_Mark", "Mc"),
+    ("Enclosing_Mark", "Me"),
+    ("Number", "N"),
+    ("Decimal_Number", "Nd"),
+    ("Letter_Number", "Nl"),
+    ("Other_Number", "No"),
+    ("Punctuation", "P"),
+    ("Connector_Punctuation", "Pc"),
+    ("Dash_Punctuation", "Pd"),
+    ("Open_Punctuation", "Ps"),
+    ("Close_Punctuation", "Pe"),
+    ("Initial_Punctuation", "Pi"),
+    ("Final_Punctuation", "Pf"),
+    ("Other_Punctuation", "Po"),
+    ("Symbol", "S"),
+    ("Math_Symbol", "Sm"),
+    ("Currency_Symbol", "Sc"),
+    ("Modifier_Symbol", "Sk"),
+    ("Other_Symbol", "So"),
+    ("Separator", "Z"),
+    ("Space_Separator", "Zs"),
+    ("Line_Separator", "Zl"),
+    ("Paragraph_Separator", "Zp"),
+    ("Other", "C"),
+    ("Control", "Cc"),
+    ("Format", "Cf"),
+    ("Surrogate", "Cs"),
+    ("Private_Use", "Co"),
+    ("Unassigned", "Cn"),
+];
+
+/**
+ * Resolve the name inside of \p{...}. It can be a General_Category, a
+ * Script or one of the binary properties above, or be qualified like
+ * gc=Lu or sc=Greek.
+ * Names match loosely as UAX #44 suggests, ignoring case, spaces, - and _.
+ */
+pub fn property(name: &str) -> Option<CharClass> {
+    if let Some((key, value)) = name.split_once('=') {
+        return match loose(key).as_str() {
+            "gc" | "generalcategory" => category_loose(value),
+            "sc" | "script" => loose_lookup(tables::SCRIPT, value).map(CharClass::from_ranges),
+            _ => None,
+        };
+    }
+    match loose(name).as_str() {
+        "any" => return Some(CharClass::from_ranges(&[('\0', std::char::MAX)])),
+        "ascii" => return Some(CharClass::from_ranges(&[('\0', '\x7F')])),
+        _ => {}
+    }
+    category_loose(name)
+        .or_else(|| loose_lookup(tables::SCRIPT, name).map(CharClass::from_ranges))
+        .or_else(|| loose_lookup(tables::BINARY_PROPERTY, name).map(CharClass::from_ranges))
+}
+
+fn category_loose(name: &str) -> Option<CharClass> {
+    let key = loose(name);
+    let short = CATEGORY_ALIASES
+        .iter()
+        .find(|(long, short)| loose(long) == key || loose(short) == key)
+        .map(|(_, short)| *short)?;
+    if short == "LC" {
+        let mut class = general_category("Lu")?;
+        class.push_class(&general_category("Ll")?);
+        class.push_class(&general_category("Lt")?);
+        return Some(class);
+    }
+    general_category(short)
+}
+
+fn loose(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, ' ' | '_' | '-'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn loose_lookup(
+    table: &'static [(&'static str, &'static [(char, char)])],
+    name: &str,
+) -> Option<&'static [(char, char)]> {
+    let key = loose(name);
+    table.iter().find(|(name, _)| loose(name) == key).map(|(_, ranges)| *ranges)
+}
+
+/**
+ * The Perl style shorthand classes, following UTS #18:
+ * \d is Nd, \s is White_Space and \w is Alphabetic, marks, Nd, Pc and
+ * Join_Control. The uppercase letters are the negations.
+ */
+pub fn perl_class(c: char) -> Option<CharClass> {
+    let mut class = match c.to_ascii_lowercase() {
+        'd' => general_category("Nd")?,
+        's' => binary_property("White_Space")?,
+        'w' => {
+            let mut class = binary_property("Alphabetic")?;
+            class.push_class(&general_category("M")?);
+            class.push_class(&general_category("Nd")?);
+            class.push_class(&general_category("Pc")?);
+            class.push_class(&binary_property("Join_Control")?);
+            class
+        }
+        _ => return None,
+    };
+    if c.is_ascii_uppercase() {
+        class.negate();
+    }
+    Some(class)
+}
+
+/**
+ * Is c a \w char? This is what \b and \B test on either side of a position.
+ */
+pub fn is_word_char(c: char) -> bool {
+    static WORD: OnceLock<CharClass> = OnceLock::new();
+    WORD.get_or_init(|| perl_class('w').unwrap()).contains(c)
+}
+
+/**
+ * Every char that matches c when case is ignored, c first. This follows
+ * the simple case folding, so ß matches ẞ but not SS, and σ, ς and Σ
+ * all match each other.
+ */
+pub fn case_variants(c: char) -> Vec<char> {
+    let mut variants = vec![c];
+    if let Ok(i) = tables::CASE_FOLDING.binary_search_by(|(key, _)| key.cmp(&c)) {
+        variants.extend(tables::CASE_FOLDING[i].1.iter().filter(|&&v| v != c));
+    }
+    variants
+}
+
+/**
+ * The class that matches what class matches when case is ignored.
+ */
+pub fn case_fold(class: &CharClass) -> CharClass {
+    let written = CharClass::from_ranges(class.ranges());
+    let mut folded = Vec::new();
+    for (c, variants) in tables::CASE_FOLDING {
+        if written.contains(*c) {
+            folded.extend(variants.iter().map(|&v| (v, v)));
+        }
+    }
+    let mut out = class.clone();
+    out.push_ranges(&folded);
+    out
+}
+
+fn lookup(
+    table: &'static [(&'static str, &'static [(char, char)])],
+    name: &str,
+) -> Option<&'static [(char, char)]> {
+    table
+        .binary_search_by(|(key, _)| (*key).cmp(name))
+        .ok()
+        .map(|i| table[i].1)
+}
+
+#[cfg(test)]
+mod unicode {
+    use super::*;
+
+    #[test]
+    fn variants() {
+        assert_eq!(case_variants('a'), vec!['a', 'A']);
+        assert_eq!(case_variants('Ж'), vec!['Ж', 'ж']);
+        assert_eq!(case_variants('7'), vec!['7']);
+        assert_eq!(case_variants('ß'), vec!['ß', 'ẞ']);
+        assert_eq!(case_variants('ς'), vec!['ς', 'Σ', 'σ']);
+        assert_eq!(case_variants('k'), vec!['k', 'K', '\u{212A}']);
+        assert_eq!(case_variants('\u{212A}'), vec!['\u{212A}', 'K', 'k']);
+    }
+
+    #[test]
+    fn folded_class() {
+        let class = case_fold(&CharClass::from_ranges(&[('a', 'c'), ('0', '9')]));
+        assert_eq!(class.ranges(), &[('0', '9'), ('A', 'C'), ('a', 'c')]);
+        let mut negated = CharClass::from_ranges(&[('x', 'x')]);
+        negated.negate();
+        let folded = case_fold(&negated);
+        assert!(!folded.contains('X'));
+        assert!(folded.contains('y'));
+    }
+
+    #[test]
+    fn properties() {
+        let letters = property("L").unwrap();
+        assert!(letters.contains('a') && letters.contains('Ж') && letters.contains('中'));
+        assert!(!letters.contains('1'));
+        assert_eq!(property("Letter"), property("l"));
+        assert_eq!(property("Nd"), general_category("Nd"));
+        assert_eq!(property("decimal number"), general_category("Nd"));
+        assert_eq!(property("gc=Lu"), general_category("Lu"));
+        let greek = property("Greek").unwrap();
+        assert!(greek.contains('λ') && greek.contains('Ω'));
+        assert!(!greek.contains('a'));
+        assert_eq!(property("sc=greek"), Some(greek));
+        assert_eq!(property("Old-Italic"), script("Old_Italic"));
+        assert_eq!(property("White_Space"), binary_property("White_Space"));
+        let cased = property("LC").unwrap();
+        assert!(cased.contains('a') && cased.contains('ǅ') && !cased.contains('ª'));
+        assert!(property("Any").unwrap().contains('\u{10FFFF}'));
+        assert_eq!(property("Klingon"), None);
+        assert_eq!(property("sc=Lu"), None);
+    }
+
+    // only the properties the hint for an unknown \p{...} promises
+    #[test]
+    fn binary_properties() {
+        let names: Vec<&str> = tables::BINARY_PROPERTY.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["Alphabetic", "Join_Control", "White_Space"]);
+        assert!(property("alphabetic").unwrap().contains('λ'));
+        assert!(property("Join-Control").unwrap().contains('\u{200D}'));
+        assert_eq!(property("Uppercase"), None);
+        assert_eq!(property("Emoji"), None);
+    }
+
+    #[test]
+    fn digits() {
+        let class = perl_class('d').unwrap();
+        assert!(class.contains('7'));
+        assert!(class.contains('٣')); // arabic-indic three
+        assert!(class.contains('७')); // devanagari seven
+        assert!(!class.contains('x'));
+        assert!(!class.contains('²'));
+    }
+
+    #[test]
+    fn words() {
+        let class = perl_class('w').unwrap();
+        assert!(class.contains('a'));
+        assert!(class.contains('_'));
+        assert!(class.contains('é'));
+        assert!(class.contains('Ж'));
+        assert!(class.contains('中'));
+        assert!(!class.contains('-'));
+        assert!(!class.contains(' '));
+    }
+
+    #[test]
+    fn spaces() {
+        let class = perl_class('s').unwrap();
+        assert!(class.contains(' '));
+        assert!(class.contains('\t'));
+        assert!(class.contains('\u{a0}'));
+        assert!(class.contains('\u{3000}'));
+        assert!(!class.contains('a'));
+    }
+
+    #[test]
+    fn negations() {
+        let class = perl_class('D').unwrap();
+        assert!(class.contains('x'));
+        assert!(!class.contains('٣'));
+        assert!(perl_class('W').unwrap().contains('-'));
+        assert!(!perl_class('S').unwrap().contains(' '));
+    }
+
+    #[test]
+    fn category_groups() {
+        let letters = general_category("L").unwrap();
+        assert!(letters.contains('a'));
+        assert!(letters.contains('Ω'));
+        assert!(!letters.contains('1'));
+        assert_eq!(general_category("Q"), None);
+        assert_eq!(general_category("Xx"), None);
+    }
+}
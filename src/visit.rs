@@ -0,0 +1,311 @@
+use super::charclass::CharClass;
+use super::parser::{alt, cat, clo, flg, lazy, opt, plus, rep, Assertion, Flags, Lookaround, AST};
+
+/*
+ * Tar Heel egrep - AST visitors
+ *
+ * Visitor walks an AST by reference and Fold rebuilds one by value. Both
+ * have a method for each kind of node that by default just recurses into
+ * its children, so an analysis or a rewrite only overrides the nodes it
+ * cares about. walk and rebuild are the only places that match on every
+ * variant, so a new kind of node has to be handled there before anything
+ * that uses them compiles.
+ */
+
+/**
+ * Walks an AST by reference, calling the method for each node it meets.
+ */
+pub trait Visitor {
+    /**
+     * Visit any node; override the methods below rather than this one.
+     */
+    fn visit(&mut self, ast: &AST) {
+        walk(self, ast)
+    }
+
+    fn visit_empty(&mut self) {}
+
+    fn visit_alternation(&mut self, lhs: &AST, rhs: &AST) {
+        self.visit(lhs);
+        self.visit(rhs);
+    }
+
+    fn visit_catenation(&mut self, lhs: &AST, rhs: &AST) {
+        self.visit(lhs);
+        self.visit(rhs);
+    }
+
+    fn visit_closure(&mut self, ast: &AST) {
+        self.visit(ast)
+    }
+
+    fn visit_one_or_more(&mut self, ast: &AST) {
+        self.visit(ast)
+    }
+
+    fn visit_optional(&mut self, ast: &AST) {
+        self.visit(ast)
+    }
+
+    fn visit_lazy(&mut self, ast: &AST) {
+        self.visit(ast)
+    }
+
+    fn visit_repeat(&mut self, ast: &AST, min: usize, max: Option<usize>) {
+        self.visit(ast)
+    }
+
+    fn visit_char(&mut self, c: char) {}
+
+    fn visit_any_char(&mut self) {}
+
+    fn visit_class(&mut self, class: &CharClass) {}
+
+    fn visit_assertion(&mut self, assertion: Assertion) {}
+
+    fn visit_backref(&mut self, index: usize) {}
+
+    fn visit_lookaround(&mut self, kind: Lookaround, ast: &AST) {
+        self.visit(ast)
+    }
+
+    fn visit_flags(&mut self, flags: Flags, ast: &AST) {
+        self.visit(ast)
+    }
+
+    fn visit_group(&mut self, index: usize, name: Option<&str>, ast: &AST) {
+        self.visit(ast)
+    }
+}
+
+/**
+ * Call the Visitor method for the kind of node ast is.
+ */
+pub fn walk<V: Visitor + ?Sized>(visitor: &mut V, ast: &AST) {
+    match ast {
+        AST::Empty => visitor.visit_empty(),
+        AST::Alternation(lhs, rhs) => visitor.visit_alternation(lhs, rhs),
+        AST::Catenation(lhs, rhs) => visitor.visit_catenation(lhs, rhs),
+        AST::Closure(ast) => visitor.visit_closure(ast),
+        AST::OneOrMore(ast) => visitor.visit_one_or_more(ast),
+        AST::Optional(ast) => visitor.visit_optional(ast),
+        AST::Lazy(ast) => visitor.visit_lazy(ast),
+        AST::Repeat { ast, min, max } => visitor.visit_repeat(ast, *min, *max),
+        AST::Char(c) => visitor.visit_char(*c),
+        AST::AnyChar => visitor.visit_any_char(),
+        AST::Class(class) => visitor.visit_class(class),
+        AST::Assertion(assertion) => visitor.visit_assertion(*assertion),
+        AST::Backref(index) => visitor.visit_backref(*index),
+        AST::Lookaround(kind, ast) => visitor.visit_lookaround(*kind, ast),
+        AST::Flags(flags, ast) => visitor.visit_flags(*flags, ast),
+        AST::Group { index, name, ast } => visitor.visit_group(*index, name.as_deref(), ast),
+    }
+}
+
+/**
+ * Rebuilds an AST by value, calling the method for each node it meets and
+ * using what it returns in place of that node.
+ */
+pub trait Fold {
+    /**
+     * Fold any node; override the methods below rather than this one.
+     */
+    fn fold(&mut self, ast: AST) -> AST {
+        rebuild(self, ast)
+    }
+
+    fn fold_empty(&mut self) -> AST {
+        AST::Empty
+    }
+
+    fn fold_alternation(&mut self, lhs: AST, rhs: AST) -> AST {
+        let lhs = self.fold(lhs);
+        alt(lhs, self.fold(rhs))
+    }
+
+    fn fold_catenation(&mut self, lhs: AST, rhs: AST) -> AST {
+        let lhs = self.fold(lhs);
+        cat(lhs, self.fold(rhs))
+    }
+
+    fn fold_closure(&mut self, ast: AST) -> AST {
+        clo(self.fold(ast))
+    }
+
+    fn fold_one_or_more(&mut self, ast: AST) -> AST {
+        plus(self.fold(ast))
+    }
+
+    fn fold_optional(&mut self, ast: AST) -> AST {
+        opt(self.fold(ast))
+    }
+
+    fn fold_lazy(&mut self, ast: AST) -> AST {
+        lazy(self.fold(ast))
+    }
+
+    fn fold_repeat(&mut self, ast: AST, min: usize, max: Option<usize>) -> AST {
+        rep(self.fold(ast), min, max)
+    }
+
+    fn fold_char(&mut self, c: char) -> AST {
+        AST::Char(c)
+    }
+
+    fn fold_any_char(&mut self) -> AST {
+        AST::AnyChar
+    }
+
+    fn fold_class(&mut self, class: CharClass) -> AST {
+        AST::Class(class)
+    }
+
+    fn fold_assertion(&mut self, assertion: Assertion) -> AST {
+        AST::Assertion(assertion)
+    }
+
+    fn fold_backref(&mut self, index: usize) -> AST {
+        AST::Backref(index)
+    }
+
+    fn fold_lookaround(&mut self, kind: Lookaround, ast: AST) -> AST {
+        AST::Lookaround(kind, Box::new(self.fold(ast)))
+    }
+
+    fn fold_flags(&mut self, flags: Flags, ast: AST) -> AST {
+        flg(flags, self.fold(ast))
+    }
+
+    fn fold_group(&mut self, index: usize, name: Option<String>, ast: AST) -> AST {
+        AST::Group {
+            index,
+            name,
+            ast: Box::new(self.fold(ast)),
+        }
+    }
+}
+
+/**
+ * Call the Fold method for the kind of node ast is.
+ */
+pub fn rebuild<F: Fold + ?Sized>(folder: &mut F, ast: AST) -> AST {
+    match ast {
+        AST::Empty => folder.fold_empty(),
+        AST::Alternation(lhs, rhs) => folder.fold_alternation(*lhs, *rhs),
+        AST::Catenation(lhs, rhs) => folder.fold_catenation(*lhs, *rhs),
+        AST::Closure(ast) => folder.fold_closure(*ast),
+        AST::OneOrMore(ast) => folder.fold_one_or_more(*ast),
+        AST::Optional(ast) => folder.fold_optional(*ast),
+        AST::Lazy(ast) => folder.fold_lazy(*ast),
+        AST::Repeat { ast, min, max } => folder.fold_repeat(*ast, min, max),
+        AST::Char(c) => folder.fold_char(c),
+        AST::AnyChar => folder.fold_any_char(),
+        AST::Class(class) => folder.fold_class(class),
+        AST::Assertion(assertion) => folder.fold_assertion(assertion),
+        AST::Backref(index) => folder.fold_backref(index),
+        AST::Lookaround(kind, ast) => folder.fold_lookaround(kind, *ast),
+        AST::Flags(flags, ast) => folder.fold_flags(flags, *ast),
+        AST::Group { index, name, ast } => folder.fold_group(index, name, *ast),
+    }
+}
+
+#[cfg(test)]
+mod visit {
+    use super::*;
+    use crate::parser::{cha, Parser};
+    use crate::tokenizer::Tokenizer;
+
+    fn parse(pattern: &str) -> AST {
+        Parser::parse(Tokenizer::new(pattern)).unwrap()
+    }
+
+    // the literal chars of a pattern and the names of its groups, in order
+    #[derive(Default)]
+    struct Literals {
+        chars: String,
+        names: Vec<String>,
+    }
+
+    impl Visitor for Literals {
+        fn visit_char(&mut self, c: char) {
+            self.chars.push(c);
+        }
+
+        fn visit_group(&mut self, index: usize, name: Option<&str>, ast: &AST) {
+            self.names.extend(name.map(String::from));
+            self.visit(ast);
+        }
+    }
+
+    #[test]
+    fn visitor() {
+        let mut literals = Literals::default();
+        literals.visit(&parse("a(?P<x>b|c*)(?=d)[e](?i:f{2})(?P<y>g)"));
+        assert_eq!(literals.chars, "abcdfg");
+        assert_eq!(literals.names, vec!["x", "y"]);
+    }
+
+    // the shortest length of a match, where only chars count
+    struct MinLength(usize);
+
+    impl Visitor for MinLength {
+        fn visit_char(&mut self, c: char) {
+            self.0 += 1;
+        }
+
+        fn visit_alternation(&mut self, lhs: &AST, rhs: &AST) {
+            let before = self.0;
+            self.visit(lhs);
+            let left = self.0 - before;
+            self.0 = before;
+            self.visit(rhs);
+            self.0 = before + left.min(self.0 - before);
+        }
+
+        fn visit_closure(&mut self, ast: &AST) {}
+
+        fn visit_optional(&mut self, ast: &AST) {}
+
+        fn visit_repeat(&mut self, ast: &AST, min: usize, max: Option<usize>) {
+            for _ in 0..min {
+                self.visit(ast);
+            }
+        }
+    }
+
+    #[test]
+    fn analysis() {
+        let mut length = MinLength(0);
+        length.visit(&parse("ab(cd|e)f*g?h{3}"));
+        assert_eq!(length.0, 6);
+    }
+
+    // every greedy quantifier made lazy
+    struct Lazify;
+
+    impl Fold for Lazify {
+        fn fold_closure(&mut self, ast: AST) -> AST {
+            lazy(clo(self.fold(ast)))
+        }
+
+        // already lazy, so only what it repeats can change
+        fn fold_lazy(&mut self, ast: AST) -> AST {
+            match ast {
+                AST::Closure(ast) => lazy(clo(self.fold(*ast))),
+                ast => lazy(self.fold(ast)),
+            }
+        }
+    }
+
+    // leaves a node as it is
+    struct Keep;
+
+    impl Fold for Keep {}
+
+    #[test]
+    fn fold() {
+        assert_eq!(Lazify.fold(parse("a*(b*|c*?)+?")).to_pattern(), "a*?(b*?|c*?)+?");
+        assert_eq!(Keep.fold(parse("(?P<n>a)[b]\\1(?<=c){2,}?")), parse("(?P<n>a)[b]\\1(?<=c){2,}?"));
+        assert_eq!(Lazify.fold(cat(cha('x'), clo(cha('y')))), cat(cha('x'), lazy(clo(cha('y')))));
+    }
+}